@@ -1,32 +1,270 @@
-// #![feature(test)]
-//
-// extern crate test;
-//
-// #[path = "../src/main.rs"]
-// mod main;
-//
-// #[cfg(test)]
-// mod tests {
-//     use test::Bencher;
-//
-//     #[bench]
-//     fn small(b: &mut Bencher) {
-//         b.iter(|| {
-//             crate::main::run("test_data/transactions.csv").unwrap();
-//         });
-//     }
-//
-//     #[bench]
-//     fn medium(b: &mut Bencher) {
-//         b.iter(|| {
-//             crate::main::run("test_data/medium_test.csv").unwrap();
-//         });
-//     }
-//
-//     // #[bench]
-//     // fn large(b: &mut Bencher) {
-//     //     b.iter(|| {
-//     //         crate::main::run("test_data/large_test.csv").unwrap();
-//     //     });
-//     // }
-// }
+//! Benchmarks for the whole run, parsing alone, processing transactions through an `Engine` alone
+//! and `Account::apply_tx` alone, over synthetic inputs from `payments_engine::generate`.
+//!
+//! The inputs are generated in memory so no fixtures are needed. `BENCH_TRANSACTIONS` sets the
+//! number of rows in the large input, e.g.
+//!
+//! `BENCH_TRANSACTIONS=1000000 cargo bench`
+//!
+//! Before the timings, the peak memory of an `Engine` that has processed each input is printed,
+//! with the tx ids as generated, moved to the top of the `u32` space and scattered across all of
+//! it.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    hint::black_box,
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use payments_engine::{
+    account::Account,
+    generate::{Generator, Mix},
+    input::{self, InputFormat},
+    transaction::{ClientId, Transaction, TxId},
+    Config, Engine,
+};
+
+/// The system allocator, counting the bytes currently allocated and the most allocated at once.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn grow(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+}
+
+/// Returns the most memory allocated at once while `f` ran, on top of what was allocated before.
+fn peak_memory<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+
+    PEAK.store(before, Ordering::Relaxed);
+    f();
+
+    PEAK.load(Ordering::Relaxed) - before
+}
+
+/// The inputs every benchmark runs against, as a name, a row count and a mix of types.
+fn inputs() -> Vec<(String, Vec<u8>)> {
+    let large = env::var("BENCH_TRANSACTIONS")
+        .ok()
+        .and_then(|transactions| transactions.parse().ok())
+        .unwrap_or(100_000);
+    let mixes = [
+        (
+            "deposits",
+            Mix {
+                deposit: 1,
+                withdrawal: 0,
+                dispute: 0,
+                resolve: 0,
+                chargeback: 0,
+            },
+        ),
+        ("mixed", Mix::default()),
+        (
+            "disputes",
+            Mix {
+                deposit: 40,
+                withdrawal: 10,
+                dispute: 30,
+                resolve: 15,
+                chargeback: 5,
+            },
+        ),
+    ];
+    let mut inputs = Vec::new();
+
+    for transactions in [1_000, large] {
+        for (name, mix) in mixes {
+            let mut csv = Vec::new();
+
+            Generator {
+                transactions,
+                mix,
+                ..Default::default()
+            }
+            .write_csv(&mut csv)
+            .unwrap();
+
+            inputs.push((format!("{name}/{transactions}"), csv));
+        }
+    }
+
+    inputs
+}
+
+fn parse(csv: &[u8]) -> Vec<Transaction> {
+    let mut txs = Vec::new();
+
+    input::read_txs(csv, InputFormat::Csv, |_, tx| {
+        txs.push(tx);
+
+        Ok(())
+    })
+    .unwrap();
+
+    txs
+}
+
+/// Prints the peak memory of an engine processing `txs`, with the ids as they are, with every id
+/// moved up so the largest is `u32::MAX` and with every id scattered over the whole `u32` space.
+fn report_memory(name: &str, txs: &[Transaction]) {
+    let offset = u32::MAX - txs.iter().map(|tx| tx.tx.0).max().unwrap_or_default();
+    let low: fn(u32, u32) -> u32 = |tx, _| tx;
+    let high: fn(u32, u32) -> u32 = |tx, offset| tx + offset;
+    // multiplying by an odd number maps every u32 to a different one, so ids stay unique and
+    // references keep finding their tx
+    let random: fn(u32, u32) -> u32 = |tx, _| tx.wrapping_mul(0x9e37_79b9);
+
+    for (ids, move_id) in [("low ids", low), ("high ids", high), ("random ids", random)] {
+        let peak = peak_memory(|| {
+            let mut engine = Engine::new();
+
+            for tx in txs {
+                let tx = Transaction {
+                    tx: TxId(move_id(tx.tx.0, offset)),
+                    ..*tx
+                };
+
+                black_box(engine.process(tx).unwrap());
+            }
+        });
+
+        println!(
+            "{name} {ids}: peak memory {:.2} MiB, {:.1} bytes per tx",
+            peak as f64 / (1024.0 * 1024.0),
+            peak as f64 / txs.len() as f64
+        );
+    }
+}
+
+/// Applies `txs` to bare accounts, with the accounts and the txs disputes reference kept in `Vec`s
+/// indexed by client and tx id, so next to nothing but `Account::apply_tx` is timed. Generated
+/// streams never reuse an id or reference another client's tx, so neither is checked.
+fn apply_txs(txs: &[Transaction], accounts: &mut [Account], history: &mut [Option<Transaction>]) {
+    for tx in txs {
+        // a u32 always fits in a usize on the targets this builds for
+        let id = tx.tx.0 as usize;
+        let referenced = match tx.requires_unique_tx() {
+            true => None,
+            false => history[id].as_mut(),
+        };
+        let outcome = accounts[usize::from(tx.client.0)].apply_tx(tx, referenced);
+
+        if outcome.is_applied() && tx.requires_history() {
+            history[id] = Some(*tx);
+        }
+
+        black_box(outcome);
+    }
+}
+
+fn benches(c: &mut Criterion) {
+    for (name, csv) in inputs() {
+        let txs = parse(&csv);
+
+        report_memory(&name, &txs);
+
+        let mut group = c.benchmark_group(name);
+
+        group.throughput(Throughput::Elements(txs.len() as u64));
+
+        group.bench_function("run", |b| {
+            b.iter(|| payments_engine::run(black_box(&csv[..]), io::sink(), &Config::default()))
+        });
+
+        group.bench_function("parse", |b| {
+            b.iter(|| {
+                input::read_txs(black_box(&csv[..]), InputFormat::Csv, |_, tx| {
+                    black_box(tx);
+
+                    Ok(())
+                })
+            })
+        });
+
+        group.bench_function("process", |b| {
+            b.iter_batched_ref(
+                Engine::new,
+                |engine| {
+                    for tx in &txs {
+                        black_box(engine.process(*tx).unwrap());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        let ids = txs
+            .iter()
+            .map(|tx| tx.tx.0 as usize + 1)
+            .max()
+            .unwrap_or_default();
+
+        group.bench_function("apply_tx", |b| {
+            b.iter_batched_ref(
+                || {
+                    let accounts: Vec<_> = (0..=u16::MAX)
+                        .map(|client| Account::new(ClientId(client)))
+                        .collect();
+
+                    (accounts, vec![None; ids])
+                },
+                |(accounts, history)| apply_txs(&txs, accounts, history),
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(bench, benches);
+criterion_main!(bench);
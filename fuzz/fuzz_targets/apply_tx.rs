@@ -0,0 +1,81 @@
+//! Feeds arbitrary transaction sequences through `Engine::process`, so they're screened exactly
+//! as a run would screen them before `Account::apply_tx` sees them. Amounts cover the whole
+//! `Decimal` range, so arithmetic that overflows panics here, and every account must stay balanced
+//! after each step.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{
+    transaction::{
+        ClientId, DisputedAmounts, Transaction, TransactionState, TransactionType, TxId,
+    },
+    DuplicatePolicy, Engine,
+};
+use rust_decimal::Decimal;
+
+#[derive(Arbitrary, Debug)]
+enum Type {
+    Chargeback,
+    Deposit,
+    Dispute,
+    Resolve,
+    Withdrawal,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Tx {
+    _type: Type,
+    // a handful of clients and ids so references actually land
+    client: u8,
+    tx: u8,
+    amount: Option<(u32, u32, u32, bool, u8)>,
+}
+
+impl Tx {
+    fn transaction(&self) -> Transaction {
+        Transaction {
+            _type: match self._type {
+                Type::Chargeback => TransactionType::Chargeback,
+                Type::Deposit => TransactionType::Deposit,
+                Type::Dispute => TransactionType::Dispute,
+                Type::Resolve => TransactionType::Resolve,
+                Type::Withdrawal => TransactionType::Withdrawal,
+            },
+            client: ClientId(u16::from(self.client % 4)),
+            tx: TxId(u32::from(self.tx % 32)),
+            amount: self.amount.map(|(lo, mid, hi, negative, scale)| {
+                Decimal::from_parts(lo, mid, hi, negative, u32::from(scale % 29))
+            }),
+            state: TransactionState::Open,
+            disputes: DisputedAmounts::default(),
+            timestamp: None,
+        }
+    }
+}
+
+fuzz_target!(|txs: Vec<Tx>| {
+    let mut engine = Engine::with_duplicates(DuplicatePolicy::Skip);
+
+    for tx in txs.iter().map(Tx::transaction) {
+        let before = engine.account(tx.client).copied();
+        let outcome = engine.process(tx).expect("duplicates are skipped");
+
+        if let Some(before) = before.filter(|account| account.locked) {
+            if tx.requires_unique_tx() {
+                assert!(!outcome.is_applied());
+                assert_eq!(Some(&before), engine.account(tx.client));
+            }
+        }
+
+        if let Some(account) = engine.account(tx.client) {
+            assert_eq!(
+                account.total,
+                account.available + account.held,
+                "{account:?}"
+            );
+            assert!(!account.held.is_sign_negative(), "{account:?}");
+        }
+    }
+});
@@ -0,0 +1,34 @@
+//! Feeds arbitrary bytes through the CSV reader into an engine. Malformed input must surface as an
+//! error rather than a panic, and whatever does parse must leave every account balanced.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{
+    input::{self, InputFormat},
+    Engine,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut engine = Engine::new();
+
+    let _ = input::read_txs(data, InputFormat::Csv, |_, tx| {
+        // the scale is kept as written, so only amounts written with at most four places pass
+        if tx.amount.is_some_and(|amount| amount.scale() > 4) {
+            assert!(!tx.valid_tx_data());
+        }
+
+        let _ = engine.process(tx);
+
+        Ok(())
+    });
+
+    for account in engine.accounts() {
+        assert_eq!(
+            account.total,
+            account.available + account.held,
+            "{account:?}"
+        );
+        assert!(!account.held.is_sign_negative(), "{account:?}");
+    }
+});
@@ -1,9 +1,20 @@
+use std::{cmp::Ordering, str::FromStr};
+
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    outcome::{TxOutcome, TxRejection},
+    transaction::{ClientId, Transaction, TransactionState, TransactionType, DECIMAL_PRECISION},
+};
 
-use crate::transaction::{ClientId, Transaction, TransactionState, TransactionType};
+/// The largest balance that still holds four places after the decimal exactly. Beyond it `Decimal`
+/// rounds away places rather than overflowing, which would quietly break `total = available +
+/// held`, so balances are kept within it.
+pub const MAX_BALANCE: Decimal =
+    Decimal::from_parts(u32::MAX, u32::MAX, u32::MAX, false, DECIMAL_PRECISION);
 
-#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Account {
     pub client: ClientId,
     pub available: Decimal,
@@ -12,6 +23,42 @@ pub struct Account {
     pub locked: bool,   // an account is locked if a charge back occurs
 }
 
+/// The order accounts are written in. Balances are sorted from largest to smallest, with ties
+/// broken by client id so that the output is always deterministic.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Client,
+    Total,
+    Available,
+}
+
+impl SortBy {
+    pub fn compare(&self, a: &Account, b: &Account) -> Ordering {
+        match self {
+            Self::Client => Ordering::Equal,
+            Self::Total => b.total.cmp(&a.total),
+            Self::Available => b.available.cmp(&a.available),
+        }
+        .then_with(|| a.client.cmp(&b.client))
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::Client),
+            "total" => Ok(Self::Total),
+            "available" => Ok(Self::Available),
+            _ => Err(format!(
+                "Unknown sort order {s}, expected client, total or available"
+            )),
+        }
+    }
+}
+
 impl Account {
     pub fn new(client: ClientId) -> Self {
         Self {
@@ -31,78 +78,220 @@ impl Account {
         }
     }
 
-    pub fn apply_tx(&mut self, tx: &Transaction, referenced_tx: Option<&mut Transaction>) {
+    /// Applies `tx` to this account, returning whether it was applied or the reason it was
+    /// rejected. Disputes, resolves and chargebacks must supply the tx they reference.
+    pub fn apply_tx(
+        &mut self,
+        tx: &Transaction,
+        referenced_tx: Option<&mut Transaction>,
+    ) -> TxOutcome {
         if self.is_locked_tx(tx) {
-            return;
+            return TxOutcome::Rejected(TxRejection::AccountLocked);
         }
 
-        match (&tx._type, referenced_tx.as_ref().map(|_ref| &_ref._type)) {
-            (TransactionType::Deposit, _) => {
-                let amount = tx.amount.unwrap_or_default();
-
-                self.available += amount;
-                self.total += amount;
+        match (&tx._type, referenced_tx) {
+            (TransactionType::Deposit, _) => self.deposit(tx.amount.unwrap_or_default()),
+            (TransactionType::Withdrawal, _) => self.withdraw(tx.amount.unwrap_or_default()),
+            (
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback,
+                None,
+            ) => TxOutcome::Rejected(TxRejection::UnknownTx),
+            (_, Some(referenced_tx))
+                if !matches!(
+                    referenced_tx._type,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                ) =>
+            {
+                TxOutcome::Rejected(TxRejection::NotDisputable)
             }
-            (TransactionType::Withdrawal, _) => {
-                let amount = tx.amount.unwrap_or_default();
-
-                if self.available >= amount {
-                    self.available -= amount;
-                    self.total -= amount;
-                }
+            (TransactionType::Dispute, Some(referenced_tx)) => {
+                self.dispute(tx.amount, referenced_tx)
             }
-            (TransactionType::Dispute, Some(TransactionType::Deposit)) => {
-                if let Some(referenced_tx) = referenced_tx {
-                    match referenced_tx.state {
-                        TransactionState::Open => {
-                            let amount = referenced_tx.amount.unwrap_or_default();
-
-                            if amount > Decimal::ZERO {
-                                referenced_tx.state = TransactionState::ActiveDispute;
-                                self.available -= amount;
-                                self.held += amount;
-                            }
-                        }
-                        TransactionState::ActiveDispute | TransactionState::ChargedBack => (),
-                    }
-                }
+            (TransactionType::Resolve, Some(referenced_tx)) => {
+                self.resolve(tx.amount, referenced_tx)
             }
-            (TransactionType::Resolve, Some(TransactionType::Deposit)) => {
-                if let Some(referenced_tx) = referenced_tx {
-                    match referenced_tx.state {
-                        TransactionState::ActiveDispute => {
-                            let amount = referenced_tx.amount.unwrap_or_default();
-
-                            if amount > Decimal::ZERO {
-                                self.available += amount;
-                                self.held -= amount;
-                                referenced_tx.state = TransactionState::Open;
-                            }
-                        }
-                        TransactionState::Open | TransactionState::ChargedBack => (),
-                    }
-                }
+            (TransactionType::Chargeback, Some(referenced_tx)) => {
+                self.chargeback(tx.amount, referenced_tx)
             }
-            (TransactionType::Chargeback, Some(TransactionType::Deposit)) => {
-                if let Some(referenced_tx) = referenced_tx {
-                    match referenced_tx.state {
-                        TransactionState::ActiveDispute => {
-                            let amount = referenced_tx.amount.unwrap_or_default();
-
-                            if amount > Decimal::ZERO {
-                                self.total -= amount;
-                                self.held -= amount;
-                                self.locked = true;
-                                referenced_tx.state = TransactionState::ChargedBack;
-                            }
-                        }
-                        TransactionState::Open | TransactionState::ChargedBack => (),
-                    }
+        }
+    }
+
+    fn deposit(&mut self, amount: Decimal) -> TxOutcome {
+        if amount.is_zero() {
+            return TxOutcome::Rejected(TxRejection::ZeroAmount);
+        }
+
+        self.adjust(amount, Decimal::ZERO, amount)
+    }
+
+    fn withdraw(&mut self, amount: Decimal) -> TxOutcome {
+        if amount.is_zero() {
+            return TxOutcome::Rejected(TxRejection::ZeroAmount);
+        }
+
+        if self.available < amount {
+            return TxOutcome::Rejected(TxRejection::InsufficientFunds);
+        }
+
+        self.adjust(-amount, Decimal::ZERO, -amount)
+    }
+
+    /// Disputes `amount` of the referenced tx, or everything that is still disputable when no
+    /// amount is given.
+    fn dispute(&mut self, amount: Option<Decimal>, referenced_tx: &mut Transaction) -> TxOutcome {
+        if referenced_tx.amount.unwrap_or_default().is_zero() {
+            return TxOutcome::Rejected(TxRejection::ZeroAmount);
+        }
+
+        let disputable = referenced_tx.disputable();
+
+        if disputable.is_zero() {
+            return match referenced_tx.state {
+                TransactionState::ChargedBack => {
+                    TxOutcome::Rejected(TxRejection::AlreadyChargedBack)
+                }
+                TransactionState::Open | TransactionState::ActiveDispute => {
+                    TxOutcome::Rejected(TxRejection::AlreadyDisputed)
                 }
+            };
+        }
+
+        let amount = amount.unwrap_or(disputable);
+
+        if amount.is_zero() {
+            return TxOutcome::Rejected(TxRejection::ZeroAmount);
+        }
+
+        if amount > disputable {
+            return TxOutcome::Rejected(TxRejection::ExceedsDisputable);
+        }
+
+        // a disputed withdrawal is held as a pending credit until it is resolved or charged back,
+        // while a disputed deposit moves from available to held
+        let outcome = if referenced_tx._type == TransactionType::Withdrawal {
+            self.adjust(Decimal::ZERO, amount, amount)
+        } else {
+            self.adjust(-amount, amount, Decimal::ZERO)
+        };
+
+        // the disputed portions are bounded by the tx amount, so they can't overflow
+        if outcome.is_applied() {
+            referenced_tx.disputes.disputed += amount;
+            referenced_tx.update_state();
+        }
+
+        outcome
+    }
+
+    /// Resolves `amount` of the active dispute on the referenced tx, or the whole active dispute
+    /// when no amount is given.
+    fn resolve(&mut self, amount: Option<Decimal>, referenced_tx: &mut Transaction) -> TxOutcome {
+        let amount = match self.disputed_amount(amount, referenced_tx) {
+            Ok(amount) => amount,
+            Err(rejection) => return TxOutcome::Rejected(rejection),
+        };
+
+        let outcome = if referenced_tx._type == TransactionType::Withdrawal {
+            self.adjust(Decimal::ZERO, -amount, -amount)
+        } else {
+            self.adjust(amount, -amount, Decimal::ZERO)
+        };
+
+        if outcome.is_applied() {
+            referenced_tx.disputes.disputed -= amount;
+            // repeated disputes and resolves can add up to more than the tx amount, and this is
+            // only a record rather than a balance
+            referenced_tx.disputes.resolved =
+                referenced_tx.disputes.resolved.saturating_add(amount);
+            referenced_tx.update_state();
+        }
+
+        outcome
+    }
+
+    /// Charges back `amount` of the active dispute on the referenced tx, or the whole active
+    /// dispute when no amount is given. Any chargeback locks the account.
+    fn chargeback(
+        &mut self,
+        amount: Option<Decimal>,
+        referenced_tx: &mut Transaction,
+    ) -> TxOutcome {
+        let amount = match self.disputed_amount(amount, referenced_tx) {
+            Ok(amount) => amount,
+            Err(rejection) => return TxOutcome::Rejected(rejection),
+        };
+
+        // charging back a withdrawal credits the client, charging back a deposit removes the
+        // funds
+        let outcome = if referenced_tx._type == TransactionType::Withdrawal {
+            self.adjust(amount, -amount, Decimal::ZERO)
+        } else {
+            self.adjust(Decimal::ZERO, -amount, -amount)
+        };
+
+        if outcome.is_applied() {
+            self.locked = true;
+            referenced_tx.disputes.disputed -= amount;
+            referenced_tx.disputes.charged_back += amount;
+            referenced_tx.update_state();
+        }
+
+        outcome
+    }
+
+    /// Adds the given deltas to `available`, `held` and `total` together. When any of them would
+    /// overflow or leave `MAX_BALANCE`, the account is left untouched and the transaction is
+    /// rejected.
+    fn adjust(&mut self, available: Decimal, held: Decimal, total: Decimal) -> TxOutcome {
+        let checked_add = |balance: Decimal, delta| {
+            balance
+                .checked_add(delta)
+                .filter(|balance| balance.abs() <= MAX_BALANCE)
+        };
+        let adjusted = (
+            checked_add(self.available, available),
+            checked_add(self.held, held),
+            checked_add(self.total, total),
+        );
+
+        match adjusted {
+            (Some(available), Some(held), Some(total)) => {
+                self.available = available;
+                self.held = held;
+                self.total = total;
+
+                TxOutcome::Applied
             }
-            (TransactionType::Chargeback, _)
-            | (TransactionType::Dispute, _)
-            | (TransactionType::Resolve, _) => (),
+            _ => TxOutcome::Rejected(TxRejection::Overflow),
+        }
+    }
+
+    /// Returns the amount a resolve or chargeback settles, which can't exceed what is actively
+    /// disputed on the referenced tx.
+    fn disputed_amount(
+        &self,
+        amount: Option<Decimal>,
+        referenced_tx: &Transaction,
+    ) -> Result<Decimal, TxRejection> {
+        let disputed = referenced_tx.disputes.disputed;
+
+        if disputed.is_zero() {
+            return match referenced_tx.state {
+                TransactionState::ChargedBack => Err(TxRejection::AlreadyChargedBack),
+                TransactionState::Open | TransactionState::ActiveDispute => {
+                    Err(TxRejection::NotDisputed)
+                }
+            };
+        }
+
+        let amount = amount.unwrap_or(disputed);
+
+        if amount.is_zero() {
+            Err(TxRejection::ZeroAmount)
+        } else if amount > disputed {
+            Err(TxRejection::ExceedsDisputed)
+        } else {
+            Ok(amount)
         }
     }
 }
@@ -0,0 +1,22 @@
+use std::{env, io, process::ExitCode};
+
+use payments_engine::{generate::Generator, EngineError};
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), EngineError> {
+    let generator = Generator::from_args(env::args().skip(1))?;
+
+    generator.write_csv(io::stdout().lock())?;
+
+    Ok(())
+}
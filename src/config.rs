@@ -0,0 +1,365 @@
+use std::{num::NonZeroUsize, path::PathBuf, str::FromStr};
+
+use crate::{
+    account::SortBy, engine::DuplicatePolicy, error::EngineError, input::InputFormat,
+    output::OutputFormat,
+};
+
+pub(crate) const USAGE: &str =
+    "Usage: payments-engine [<transactions.csv> | -] [--input-format csv|jsonl] \
+                     [--output <accounts.csv>] [--output-format csv|json|jsonl] \
+                     [--rejected <rejected.csv>] [--sort client|total|available] \
+                     [--threads <n>] [--on-duplicate abort|skip|report] \
+                     [--load-snapshot <snapshot.jsonl>] [--save-snapshot <snapshot.jsonl>] \
+                     [--journal <journal.jsonl>] [--store <dir>] \
+                     [--dispute-window <seconds>]";
+
+/// Options for a single run of the engine, usually parsed from the command line.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// Path to the transactions CSV, or `None` to read from stdin.
+    pub input: Option<PathBuf>,
+    /// The format of the transactions being read.
+    pub input_format: InputFormat,
+    /// Path the accounts are written to, or `None` to write to stdout.
+    pub output: Option<PathBuf>,
+    /// The format accounts are written in.
+    pub output_format: OutputFormat,
+    /// Optional path where every rejected transaction is reported.
+    pub rejected: Option<PathBuf>,
+    /// The order accounts are written in.
+    pub sort: SortBy,
+    /// The number of worker threads clients are sharded across, or `None` to process every
+    /// transaction on the calling thread.
+    pub threads: Option<NonZeroUsize>,
+    /// What happens when a deposit or withdrawal reuses a tx id. `Report` needs `rejected`.
+    pub on_duplicate: DuplicatePolicy,
+    /// Optional path of a snapshot to continue from, instead of starting with an empty ledger.
+    pub load_snapshot: Option<PathBuf>,
+    /// Optional path the ledger is snapshotted to once every transaction is processed.
+    pub save_snapshot: Option<PathBuf>,
+    /// Optional path of a write-ahead journal that an interrupted run is resumed from. Can't be
+    /// combined with `threads`.
+    pub journal: Option<PathBuf>,
+    /// Optional directory the accounts and history are kept in on disk, instead of in memory, for
+    /// a history that doesn't fit in memory. The ledger persists across runs. Can't be combined
+    /// with `threads`.
+    pub store: Option<PathBuf>,
+    /// The most seconds a dispute may be timestamped after the tx it references, or `None` for no
+    /// limit. Transactions without a timestamp are never outside the window.
+    pub dispute_window: Option<u64>,
+}
+
+impl Config {
+    /// Parses the arguments that follow the program name. A missing input path or `-` reads the
+    /// transactions from stdin.
+    pub fn from_args<I>(args: I) -> Result<Self, EngineError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut input = None::<String>;
+        let mut input_format = InputFormat::default();
+        let mut output = None;
+        let mut output_format = OutputFormat::default();
+        let mut rejected = None;
+        let mut sort = SortBy::default();
+        let mut threads = None;
+        let mut on_duplicate = DuplicatePolicy::default();
+        let mut load_snapshot = None;
+        let mut save_snapshot = None;
+        let mut journal = None;
+        let mut store = None;
+        let mut dispute_window = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input-format" => input_format = Self::parsed(&arg, args.next())?,
+                "--output" => output = Some(Self::value(&arg, args.next())?.into()),
+                "--output-format" => output_format = Self::parsed(&arg, args.next())?,
+                "--rejected" => rejected = Some(Self::value(&arg, args.next())?.into()),
+                "--sort" => sort = Self::parsed(&arg, args.next())?,
+                "--threads" => {
+                    let value = Self::value(&arg, args.next())?;
+                    let parsed = value.parse().map_err(|_| {
+                        usage_error(format!("{arg} expects a positive integer, got {value}"))
+                    })?;
+
+                    threads = Some(parsed);
+                }
+                "--on-duplicate" => on_duplicate = Self::parsed(&arg, args.next())?,
+                "--load-snapshot" => load_snapshot = Some(Self::value(&arg, args.next())?.into()),
+                "--save-snapshot" => save_snapshot = Some(Self::value(&arg, args.next())?.into()),
+                "--journal" => journal = Some(Self::value(&arg, args.next())?.into()),
+                "--store" => store = Some(Self::value(&arg, args.next())?.into()),
+                "--dispute-window" => {
+                    let value = Self::value(&arg, args.next())?;
+                    let parsed = value.parse().map_err(|_| {
+                        usage_error(format!("{arg} expects a number of seconds, got {value}"))
+                    })?;
+
+                    dispute_window = Some(parsed);
+                }
+                _ if arg.starts_with("--") || input.is_some() => {
+                    return Err(usage_error(format!("Unexpected argument {arg}")));
+                }
+                _ => input = Some(arg),
+            }
+        }
+
+        let input = input.filter(|input| input != "-").map(PathBuf::from);
+        let config = Self {
+            input,
+            input_format,
+            output,
+            output_format,
+            rejected,
+            sort,
+            threads,
+            on_duplicate,
+            load_snapshot,
+            save_snapshot,
+            journal,
+            store,
+            dispute_window,
+        };
+
+        config.check()?;
+
+        Ok(config)
+    }
+
+    /// Returns an error for options that can't be combined, before any file is opened for them.
+    pub(crate) fn check(&self) -> Result<(), EngineError> {
+        if self.on_duplicate == DuplicatePolicy::Report && self.rejected.is_none() {
+            return Err(usage_error("--on-duplicate report requires --rejected"));
+        }
+
+        if self.journal.is_some() && self.threads.is_some() {
+            return Err(usage_error("--journal can't be combined with --threads"));
+        }
+
+        if self.store.is_some() && self.threads.is_some() {
+            return Err(usage_error("--store can't be combined with --threads"));
+        }
+
+        Ok(())
+    }
+
+    fn value(flag: &str, value: Option<String>) -> Result<String, EngineError> {
+        value.ok_or_else(|| usage_error(format!("{flag} requires a value")))
+    }
+
+    fn parsed<T>(flag: &str, value: Option<String>) -> Result<T, EngineError>
+    where
+        T: FromStr<Err = String>,
+    {
+        Self::value(flag, value)?.parse().map_err(usage_error)
+    }
+}
+
+fn usage_error<S: Into<String>>(message: S) -> EngineError {
+    EngineError::Usage {
+        message: message.into(),
+        usage: USAGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn input_only() {
+        let actual = Config::from_args(args(&["transactions.csv"])).unwrap();
+
+        assert_eq!(
+            Config {
+                input: Some(PathBuf::from("transactions.csv")),
+                input_format: InputFormat::Csv,
+                output: None,
+                output_format: OutputFormat::Csv,
+                rejected: None,
+                sort: SortBy::Client,
+                threads: None,
+                on_duplicate: DuplicatePolicy::Abort,
+                load_snapshot: None,
+                save_snapshot: None,
+                journal: None,
+                store: None,
+                dispute_window: None,
+            },
+            actual
+        );
+    }
+
+    #[test]
+    fn output_files() {
+        let actual = Config::from_args(args(&[
+            "--rejected",
+            "rejected.csv",
+            "transactions.csv",
+            "--output",
+            "accounts.csv",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            Config {
+                input: Some(PathBuf::from("transactions.csv")),
+                input_format: InputFormat::Csv,
+                output: Some(PathBuf::from("accounts.csv")),
+                output_format: OutputFormat::Csv,
+                rejected: Some(PathBuf::from("rejected.csv")),
+                sort: SortBy::Client,
+                threads: None,
+                on_duplicate: DuplicatePolicy::Abort,
+                load_snapshot: None,
+                save_snapshot: None,
+                journal: None,
+                store: None,
+                dispute_window: None,
+            },
+            actual
+        );
+    }
+
+    #[test]
+    fn stdin_input() {
+        assert_eq!(Config::default(), Config::from_args(args(&[])).unwrap());
+        assert_eq!(Config::default(), Config::from_args(args(&["-"])).unwrap());
+    }
+
+    #[test]
+    fn input_format() {
+        let actual = Config::from_args(args(&["--input-format", "jsonl"])).unwrap();
+
+        assert_eq!(InputFormat::Jsonl, actual.input_format);
+    }
+
+    #[test]
+    fn output_format() {
+        let actual = Config::from_args(args(&["--output-format", "json"])).unwrap();
+
+        assert_eq!(OutputFormat::Json, actual.output_format);
+
+        let actual = Config::from_args(args(&["--output-format", "jsonl"])).unwrap();
+
+        assert_eq!(OutputFormat::Jsonl, actual.output_format);
+    }
+
+    #[test]
+    fn sort_order() {
+        let actual = Config::from_args(args(&["--sort", "total"])).unwrap();
+
+        assert_eq!(SortBy::Total, actual.sort);
+
+        let actual = Config::from_args(args(&["--sort", "available"])).unwrap();
+
+        assert_eq!(SortBy::Available, actual.sort);
+    }
+
+    #[test]
+    fn threads() {
+        let actual = Config::from_args(args(&["--threads", "4"])).unwrap();
+
+        assert_eq!(NonZeroUsize::new(4), actual.threads);
+    }
+
+    #[test]
+    fn on_duplicate() {
+        let actual = Config::from_args(args(&["--on-duplicate", "skip"])).unwrap();
+
+        assert_eq!(DuplicatePolicy::Skip, actual.on_duplicate);
+
+        let actual = Config::from_args(args(&[
+            "--on-duplicate",
+            "report",
+            "--rejected",
+            "rejected.csv",
+        ]))
+        .unwrap();
+
+        assert_eq!(DuplicatePolicy::Report, actual.on_duplicate);
+    }
+
+    #[test]
+    fn snapshots() {
+        let actual = Config::from_args(args(&[
+            "--load-snapshot",
+            "day-1.json",
+            "--save-snapshot",
+            "day-2.json",
+        ]))
+        .unwrap();
+
+        assert_eq!(Some(PathBuf::from("day-1.json")), actual.load_snapshot);
+        assert_eq!(Some(PathBuf::from("day-2.json")), actual.save_snapshot);
+    }
+
+    #[test]
+    fn journal() {
+        let actual = Config::from_args(args(&["--journal", "journal.jsonl"])).unwrap();
+
+        assert_eq!(Some(PathBuf::from("journal.jsonl")), actual.journal);
+    }
+
+    #[test]
+    fn store() {
+        let actual = Config::from_args(args(&["--store", "ledger"])).unwrap();
+
+        assert_eq!(Some(PathBuf::from("ledger")), actual.store);
+    }
+
+    #[test]
+    fn dispute_window() {
+        let actual = Config::from_args(args(&["--dispute-window", "86400"])).unwrap();
+
+        assert_eq!(Some(86_400), actual.dispute_window);
+    }
+
+    #[test]
+    fn invalid_args() {
+        assert!(Config::from_args(args(&["a.csv", "b.csv"])).is_err());
+        assert!(Config::from_args(args(&["-", "b.csv"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--rejected"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--output"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--sort", "held"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--input-format", "xml"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--output-format", "xml"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--threads", "0"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--threads", "many"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--on-duplicate", "keep"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--on-duplicate", "report"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--load-snapshot"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--save-snapshot"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--journal"])).is_err());
+        assert!(Config::from_args(args(&[
+            "a.csv",
+            "--journal",
+            "journal.jsonl",
+            "--threads",
+            "2"
+        ]))
+        .is_err());
+        assert!(Config::from_args(args(&["a.csv", "--store"])).is_err());
+        assert!(
+            Config::from_args(args(&["a.csv", "--store", "ledger", "--threads", "2"])).is_err()
+        );
+        assert!(Config::from_args(args(&["a.csv", "--dispute-window", "-1"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--dispute-window", "1d"])).is_err());
+        assert!(Config::from_args(args(&["a.csv", "--unknown"])).is_err());
+
+        match Config::from_args(args(&["a.csv", "--sort", "held"])) {
+            Err(EngineError::Usage { message, usage }) => {
+                assert!(message.contains("held"), "{message}");
+                assert_eq!(USAGE, usage);
+            }
+            other => panic!("expected a usage error, got {other:?}"),
+        }
+    }
+}
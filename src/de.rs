@@ -0,0 +1,430 @@
+//! Whitespace tolerant parsers for transaction fields along with the serde deserializers built on
+//! them.
+//!
+//! A field may be padded with whitespace on either side, but anything else around the value, such
+//! as `"1.2asdf"` or `"1.2    1"`, is rejected. Parsing never allocates: surrounding whitespace is
+//! skipped and the value is parsed in place. This lets the CSV reader skip `csv::Trim::All`, which
+//! was where most of the runtime went according to the flamegraph.
+
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{
+    de::{self, Unexpected, Visitor},
+    Deserializer,
+};
+
+use crate::transaction::{Timestamp, TransactionType};
+
+/// Parses a decimal such as `"  1.2 "`. The scale is kept as written, so `"1.00000"` has five
+/// places and fails the precision check later on like any other excess precision amount.
+pub fn parse_decimal(field: &[u8]) -> Option<Decimal> {
+    let token = field.trim_ascii();
+    let digits = token.strip_prefix(b"-").unwrap_or(token);
+    let (whole, fraction) = match digits.iter().position(|&b| b == b'.') {
+        Some(index) => (&digits[..index], &digits[index + 1..]),
+        None => (digits, &b""[..]),
+    };
+
+    // `Decimal::from_str` is more lenient than a CSV field should be, e.g. it skips underscores
+    let is_number = !(whole.is_empty() && fraction.is_empty())
+        && whole.iter().chain(fraction).all(u8::is_ascii_digit);
+
+    if !is_number {
+        return None;
+    }
+
+    let token = std::str::from_utf8(token).ok()?;
+
+    Decimal::from_str(token).ok()
+}
+
+/// Parses an optional amount. An empty or whitespace only field is `None`.
+pub fn parse_amount(field: &[u8]) -> Option<Option<Decimal>> {
+    if field.trim_ascii().is_empty() {
+        Some(None)
+    } else {
+        parse_decimal(field).map(Some)
+    }
+}
+
+/// Parses an optional timestamp in seconds since the Unix epoch, such as `" 1700000000"`. An empty
+/// or whitespace only field is `None`.
+pub fn parse_timestamp(field: &[u8]) -> Option<Option<Timestamp>> {
+    if field.trim_ascii().is_empty() {
+        Some(None)
+    } else {
+        parse_id(field).map(|seconds| Some(Timestamp(seconds)))
+    }
+}
+
+/// Parses an unsigned id such as `" 42"` that fits in `T`.
+pub fn parse_id<T>(field: &[u8]) -> Option<T>
+where
+    T: FromStr,
+{
+    let token = field.trim_ascii();
+
+    if token.is_empty() || !token.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// Parses a lowercase transaction type such as `"deposit "`.
+pub fn parse_tx_type(field: &[u8]) -> Option<TransactionType> {
+    match field.trim_ascii() {
+        b"chargeback" => Some(TransactionType::Chargeback),
+        b"deposit" => Some(TransactionType::Deposit),
+        b"dispute" => Some(TransactionType::Dispute),
+        b"resolve" => Some(TransactionType::Resolve),
+        b"withdrawal" => Some(TransactionType::Withdrawal),
+        _ => None,
+    }
+}
+
+/// Deserializes an unsigned id from either an integer or a whitespace padded string.
+pub(crate) fn id<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + TryFrom<u64>,
+{
+    deserializer.deserialize_any(IdVisitor(PhantomData))
+}
+
+struct IdVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for IdVisitor<T>
+where
+    T: FromStr + TryFrom<u64>,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an unsigned integer id")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        T::try_from(value).map_err(|_| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        u64::try_from(value)
+            .map_err(|_| E::invalid_value(Unexpected::Signed(value), &self))
+            .and_then(|value| self.visit_u64(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        parse_id(value.as_bytes()).ok_or_else(|| E::invalid_value(Unexpected::Str(value), &self))
+    }
+}
+
+/// Deserializes an optional amount from a number, a whitespace padded string, or nothing at all.
+pub(crate) fn amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(AmountVisitor)
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Option<Decimal>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an optional decimal amount")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Some(Decimal::from(value)))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Some(Decimal::from(value)))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        // the shortest representation that round trips, which is what the producer most likely
+        // wrote. It can't be more precise than the float though, which is why the JSONL reader
+        // parses numeric amounts from their text instead
+        parse_decimal(value.to_string().as_bytes())
+            .map(Some)
+            .ok_or_else(|| E::invalid_value(Unexpected::Float(value), &self))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        parse_amount(value.as_bytes())
+            .ok_or_else(|| E::invalid_value(Unexpected::Str(value), &self))
+    }
+}
+
+/// Deserializes an optional timestamp from an integer, a whitespace padded string, or nothing at
+/// all.
+pub(crate) fn timestamp<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(TimestampVisitor)
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Option<Timestamp>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "an optional number of seconds since the Unix epoch"
+        )
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        u32::try_from(value)
+            .ok()
+            .map(|seconds| Some(Timestamp(seconds)))
+            .ok_or_else(|| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        u64::try_from(value)
+            .map_err(|_| E::invalid_value(Unexpected::Signed(value), &self))
+            .and_then(|value| self.visit_u64(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        parse_timestamp(value.as_bytes())
+            .ok_or_else(|| E::invalid_value(Unexpected::Str(value), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::{ClientId, Transaction, TxId};
+
+    #[test]
+    fn decimal_examples() {
+        assert_eq!(Some(dec!(1.2)), parse_decimal(b"1.2"));
+        assert_eq!(Some(dec!(1.2)), parse_decimal(b"    1.2"));
+        assert_eq!(Some(dec!(1.2)), parse_decimal(b"    1.2    "));
+        assert_eq!(None, parse_decimal(b"    1.2    1"));
+        assert_eq!(None, parse_decimal(b"    1.2asdf"));
+    }
+
+    #[test]
+    fn decimal_edge_cases() {
+        assert_eq!(Some(dec!(-1.5)), parse_decimal(b"\t-1.5\t"));
+        assert_eq!(Some(dec!(0.5)), parse_decimal(b".5"));
+        assert_eq!(Some(dec!(2)), parse_decimal(b"2."));
+        assert_eq!(4, parse_decimal(b"2.0000").unwrap().scale());
+        assert_eq!(5, parse_decimal(b"1.00000").unwrap().scale());
+        assert_eq!(
+            Some(dec!(1.000012312312312)),
+            parse_decimal(b"1.000012312312312")
+        );
+        assert_eq!(None, parse_decimal(b""));
+        assert_eq!(None, parse_decimal(b"."));
+        assert_eq!(None, parse_decimal(b"-"));
+        assert_eq!(None, parse_decimal(b"1_000"));
+        assert_eq!(None, parse_decimal(b"1e5"));
+        assert_eq!(None, parse_decimal(b"+1"));
+        assert_eq!(None, parse_decimal(b"1.2.3"));
+    }
+
+    #[test]
+    fn amount_examples() {
+        assert_eq!(Some(None), parse_amount(b""));
+        assert_eq!(Some(None), parse_amount(b"    "));
+        assert_eq!(Some(Some(dec!(1.2))), parse_amount(b" 1.2 "));
+        assert_eq!(None, parse_amount(b" 1.2 x"));
+    }
+
+    #[test]
+    fn timestamp_examples() {
+        assert_eq!(Some(None), parse_timestamp(b""));
+        assert_eq!(Some(None), parse_timestamp(b"    "));
+        assert_eq!(
+            Some(Some(Timestamp(1_700_000_000))),
+            parse_timestamp(b" 1700000000 ")
+        );
+        assert_eq!(None, parse_timestamp(b"2024-01-01T00:00:00Z"));
+        assert_eq!(None, parse_timestamp(b"-1"));
+        assert_eq!(
+            Some(Some(Timestamp(u32::MAX))),
+            parse_timestamp(b"4294967295")
+        );
+        assert_eq!(None, parse_timestamp(b"4294967296"));
+    }
+
+    #[test]
+    fn id_examples() {
+        assert_eq!(Some(1u16), parse_id(b"1"));
+        assert_eq!(Some(1u16), parse_id(b"    1"));
+        assert_eq!(Some(1u32), parse_id(b"    1    "));
+        assert_eq!(Some(u16::MAX), parse_id(b"65535"));
+        assert_eq!(None, parse_id::<u16>(b"65536"));
+        assert_eq!(None, parse_id::<u32>(b"    1    1"));
+        assert_eq!(None, parse_id::<u32>(b"    1asdf"));
+        assert_eq!(None, parse_id::<u32>(b"-1"));
+        assert_eq!(None, parse_id::<u32>(b"+1"));
+        assert_eq!(None, parse_id::<u32>(b"  "));
+    }
+
+    #[test]
+    fn tx_type_examples() {
+        assert_eq!(Some(TransactionType::Deposit), parse_tx_type(b"deposit"));
+        assert_eq!(
+            Some(TransactionType::Withdrawal),
+            parse_tx_type(b"  withdrawal  ")
+        );
+        assert_eq!(None, parse_tx_type(b"Deposit"));
+        assert_eq!(None, parse_tx_type(b"deposit x"));
+    }
+
+    #[test]
+    fn serde_examples() {
+        let actual: Transaction = serde_json::from_str(
+            r#"{"type": "deposit", "client": "    1", "tx": 2, "amount": "    1.2    "}"#,
+        )
+        .unwrap();
+
+        assert_eq!(ClientId(1u16), actual.client);
+        assert_eq!(TxId(2u32), actual.tx);
+        assert_eq!(Some(dec!(1.2)), actual.amount);
+
+        let actual: Transaction =
+            serde_json::from_str(r#"{"type": "dispute", "client": 1, "tx": 2, "amount": null}"#)
+                .unwrap();
+
+        assert_eq!(None, actual.amount);
+        assert_eq!(None, actual.timestamp);
+
+        for timestamp in ["5", r#"" 5 ""#] {
+            let actual: Transaction = serde_json::from_str(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 2, "amount": 1, "timestamp": {timestamp}}}"#
+            ))
+            .unwrap();
+
+            assert_eq!(Some(Timestamp(5)), actual.timestamp, "{timestamp}");
+        }
+
+        for timestamp in ["4294967295", r#""4294967295""#] {
+            let actual: Transaction = serde_json::from_str(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 2, "amount": 1, "timestamp": {timestamp}}}"#
+            ))
+            .unwrap();
+
+            assert_eq!(Some(Timestamp(u32::MAX)), actual.timestamp, "{timestamp}");
+        }
+
+        for timestamp in ["null", r#""""#] {
+            let actual: Transaction = serde_json::from_str(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 2, "amount": 1, "timestamp": {timestamp}}}"#
+            ))
+            .unwrap();
+
+            assert_eq!(None, actual.timestamp, "{timestamp}");
+        }
+
+        for timestamp in ["4294967296", r#""4294967296""#, "-1"] {
+            let actual = serde_json::from_str::<Transaction>(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 2, "amount": 1, "timestamp": {timestamp}}}"#
+            ));
+
+            assert!(actual.is_err(), "{timestamp}");
+        }
+
+        for amount in [r#""    1.2    1""#, r#""    1.2asdf""#, "true"] {
+            let actual = serde_json::from_str::<Transaction>(&format!(
+                r#"{{"type": "deposit", "client": 1, "tx": 2, "amount": {amount}}}"#
+            ));
+
+            assert!(actual.is_err(), "{amount}");
+        }
+
+        for client in [r#""1asdf""#, "-1", "65536"] {
+            let actual = serde_json::from_str::<Transaction>(&format!(
+                r#"{{"type": "deposit", "client": {client}, "tx": 2, "amount": 1}}"#
+            ));
+
+            assert!(actual.is_err(), "{client}");
+        }
+    }
+}
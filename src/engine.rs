@@ -0,0 +1,874 @@
+use std::{io, num::NonZeroUsize, str::FromStr};
+
+use crate::{
+    account::{Account, SortBy},
+    error::EngineError,
+    outcome::{TxOutcome, TxRejection},
+    report::RejectedTx,
+    sharded,
+    snapshot::{self, Record, StoredClaim},
+    store::{LedgerStore, MemoryStore},
+    transaction::{ClientId, Transaction, TransactionType, TxId},
+};
+
+/// The payments engine. It owns every client `Account` along with the history of transactions
+/// that later disputes, resolves and chargebacks are able to reference and the tx ids claimed so
+/// far, all kept in a `LedgerStore`.
+#[derive(Debug, Default)]
+pub struct Engine<S = MemoryStore> {
+    store: S,
+    duplicates: DuplicatePolicy,
+    dispute_window: Option<u64>,
+    line: u64,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine that handles a reused deposit or withdrawal tx id as `duplicates` says.
+    pub fn with_duplicates(duplicates: DuplicatePolicy) -> Self {
+        Self::with_store(MemoryStore::default(), duplicates)
+    }
+
+    /// Creates an engine that continues from the snapshot read from `snapshot`, as if it had
+    /// processed every transaction the snapshotted engine did.
+    pub fn from_snapshot<R: io::Read>(
+        snapshot: R,
+        duplicates: DuplicatePolicy,
+    ) -> Result<Self, EngineError> {
+        Self::restore(MemoryStore::default(), snapshot, duplicates)
+    }
+
+    /// Splits the engine into one that only keeps the claimed tx ids, which screens transactions
+    /// for the others, and an engine per shard, each holding the accounts and history of the
+    /// clients `sharded::shard` assigns to it.
+    pub(crate) fn into_shards(self, threads: NonZeroUsize) -> (Engine, Vec<Engine>) {
+        let mut shards: Vec<_> = (0..threads.get())
+            .map(|_| Engine {
+                dispute_window: self.dispute_window,
+                ..Engine::new()
+            })
+            .collect();
+        let MemoryStore {
+            accounts,
+            transactions,
+            claims,
+        } = self.store;
+
+        for (client, account) in accounts {
+            shards[sharded::shard(client, threads)]
+                .store
+                .accounts
+                .insert(client, account);
+        }
+
+        for (tx, record) in transactions.into_records() {
+            shards[sharded::shard(record.client, threads)]
+                .store
+                .transactions
+                .insert(tx, record);
+        }
+
+        let screen = Engine {
+            store: MemoryStore {
+                claims,
+                ..MemoryStore::default()
+            },
+            ..self
+        };
+
+        (screen, shards)
+    }
+
+    /// Puts an engine split by `into_shards` back together.
+    pub(crate) fn from_shards(mut screen: Engine, shards: Vec<Engine>) -> Self {
+        for shard in shards {
+            screen.store.accounts.extend(shard.store.accounts);
+
+            for (tx, record) in shard.store.transactions.into_records() {
+                screen.store.transactions.insert(tx, record);
+            }
+        }
+
+        screen
+    }
+
+    /// Returns the account for `client`, if any transaction has been processed for it.
+    pub fn account(&self, client: ClientId) -> Option<&Account> {
+        self.store.accounts.get(&client)
+    }
+
+    /// Returns an iterator over every account in the ledger in no particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.store.accounts.values()
+    }
+
+    /// Returns every account in the ledger in the order given by `sort`.
+    pub fn sorted_accounts(&self, sort: SortBy) -> Vec<&Account> {
+        let mut accounts: Vec<_> = self.accounts().collect();
+
+        accounts.sort_unstable_by(|a, b| sort.compare(a, b));
+
+        accounts
+    }
+}
+
+impl<S: LedgerStore> Engine<S> {
+    /// Creates an engine that keeps its ledger in `store` and handles a reused deposit or
+    /// withdrawal tx id as `duplicates` says.
+    pub fn with_store(store: S, duplicates: DuplicatePolicy) -> Self {
+        Self {
+            store,
+            duplicates,
+            dispute_window: None,
+            line: 0,
+        }
+    }
+
+    /// Returns an engine with the same settings, at the same line, that keeps its ledger in
+    /// `store` instead.
+    pub(crate) fn over<T: LedgerStore>(&self, store: T) -> Engine<T> {
+        Engine {
+            store,
+            duplicates: self.duplicates,
+            dispute_window: self.dispute_window,
+            line: self.line,
+        }
+    }
+
+    /// Rejects disputes raised more than `window` seconds after the deposit or withdrawal they
+    /// reference. A dispute is never too late when either transaction has no timestamp.
+    pub fn with_dispute_window(self, window: Option<u64>) -> Self {
+        Self {
+            dispute_window: window,
+            ..self
+        }
+    }
+
+    /// Creates an engine that keeps its ledger in `store` and continues from the snapshot read
+    /// from `snapshot`, as if it had processed every transaction the snapshotted engine did. Each
+    /// record goes into `store` as soon as it's read.
+    pub fn restore<R: io::Read>(
+        mut store: S,
+        snapshot: R,
+        duplicates: DuplicatePolicy,
+    ) -> Result<Self, EngineError> {
+        snapshot::read(snapshot, |record| {
+            match record {
+                Record::Account(account) => store.insert_account(account),
+                Record::Tx(tx) => {
+                    let tx = Transaction::from(tx);
+
+                    store.insert_tx(tx.tx, (&tx).into())
+                }
+                Record::Claim(claim) => store.insert_claim(claim.tx, None),
+                Record::End => unreachable!("the end of a snapshot is never handed over"),
+            }
+            .and_then(|()| store.checkpoint(None))
+            .map_err(Into::into)
+        })?;
+
+        Ok(Self::with_store(store, duplicates))
+    }
+
+    /// Writes a snapshot of every account, the history and the claimed tx ids, without the lines
+    /// they were claimed on, to `output`, ordered so the same engine always gives the same
+    /// snapshot. The history and claims are streamed out of the store as they're written.
+    pub fn write_snapshot<W: io::Write>(&self, output: W) -> Result<(), EngineError> {
+        let mut accounts = self.store.accounts().map_err(Into::into)?;
+
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        let accounts = accounts
+            .into_iter()
+            .map(|account| Ok(Record::Account(account)));
+        let transactions = self.store.transactions().map(|stored| {
+            let (tx, record) = stored.map_err(Into::into)?;
+
+            Ok(Record::Tx((&record.transaction(tx)).into()))
+        });
+        let claims = self.store.claims().map(|claim| {
+            let (tx, _) = claim.map_err(Into::into)?;
+
+            Ok(Record::Claim(StoredClaim { tx }))
+        });
+
+        snapshot::write(output, accounts.chain(transactions).chain(claims))
+    }
+
+    /// Returns the store the ledger is kept in.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Writes everything out to the store and marks the run as finished, so the next engine over
+    /// the same store starts on a new input. Until then, a store that persists across runs is only
+    /// as far along as its last checkpoint.
+    pub fn finish(&mut self) -> Result<(), EngineError> {
+        self.store.finish().map_err(Into::into)
+    }
+
+    /// Applies a single transaction to the ledger and returns its outcome. Transactions with
+    /// invalid data and transactions that reference a tx belonging to a different client are
+    /// rejected. A deposit or withdrawal that reuses an existing tx id is an error, unless the
+    /// engine was created with a `DuplicatePolicy` other than `Abort`.
+    ///
+    /// Transactions are numbered in the order they're processed, which is what a duplicate tx
+    /// error reports as their lines. Use `process_line` when the transactions come with lines.
+    pub fn process(&mut self, tx: Transaction) -> Result<TxOutcome, EngineError> {
+        self.process_line(self.line + 1, tx)
+    }
+
+    /// Same as `process`, for a transaction read from `line` of its input.
+    pub fn process_line(&mut self, line: u64, tx: Transaction) -> Result<TxOutcome, EngineError> {
+        // a transaction that stops the run still takes up its line
+        self.line = line;
+
+        let change = self.prepare(line, &tx)?;
+        let outcome = change.outcome;
+
+        self.commit(line, change).map_err(Into::into)?;
+
+        Ok(outcome)
+    }
+
+    /// Works out what processing `tx`, read from `line`, changes without changing anything, so
+    /// the change can be journaled before it's committed.
+    pub(crate) fn prepare(&self, line: u64, tx: &Transaction) -> Result<Change, EngineError> {
+        if let Some(reason) = self.check(line, tx)? {
+            return Ok(Change::rejected(reason));
+        }
+
+        let mut change = self.change(tx).map_err(Into::into)?;
+
+        if tx.requires_unique_tx() {
+            change.claim = Some(tx.tx);
+        }
+
+        Ok(change)
+    }
+
+    /// Makes the change `prepare` worked out for a transaction read from `line`, which ends a
+    /// checkpoint of the store.
+    pub(crate) fn commit(&mut self, line: u64, change: Change) -> Result<(), S::Error> {
+        self.line = line;
+
+        if let Some(tx) = change.claim {
+            self.store.insert_claim(tx, Some(line))?;
+        }
+
+        if let Some(account) = change.account {
+            match self.store.account_mut(account.client)? {
+                Some(stored) => *stored = account,
+                None => self.store.insert_account(account)?,
+            }
+        }
+
+        if let Some(stored) = change.stored {
+            self.store.insert_tx(stored.tx, (&stored).into())?;
+        }
+
+        self.store.checkpoint(Some(line))
+    }
+
+    /// Applies a transaction that has already been screened to the ledger.
+    pub(crate) fn apply(&mut self, tx: Transaction) -> Result<TxOutcome, S::Error> {
+        let change = self.change(&tx)?;
+        let outcome = change.outcome;
+
+        self.commit(self.line, change)?;
+
+        Ok(outcome)
+    }
+
+    /// Works out what applying a transaction that has already been screened changes. A
+    /// dispute, resolve or chargeback that doesn't reference a stored tx of its own client is
+    /// rejected without creating an account.
+    fn change(&self, tx: &Transaction) -> Result<Change, S::Error> {
+        let mut referenced = match tx.requires_unique_tx() {
+            // deposits and withdrawals never find a tx, their ids are unique
+            true => None,
+            false => match self.store.tx(tx.tx)? {
+                Some(record) if record.client == tx.client => Some(record.transaction(tx.tx)),
+                Some(_) => return Ok(Change::rejected(TxRejection::ClientMismatch)),
+                None => return Ok(Change::rejected(TxRejection::UnknownTx)),
+            },
+        };
+
+        let expired = match (tx._type, &referenced, self.dispute_window) {
+            (TransactionType::Dispute, Some(referenced), Some(window)) => tx
+                .seconds_since(referenced)
+                .is_some_and(|elapsed| elapsed > window),
+            _ => false,
+        };
+
+        let mut account = self
+            .store
+            .account(tx.client)?
+            .unwrap_or_else(|| Account::new(tx.client));
+        let outcome = match expired {
+            true => TxOutcome::Rejected(TxRejection::DisputeWindowExpired),
+            false => account.apply_tx(tx, referenced.as_mut()),
+        };
+
+        // only applied txs can be referenced, otherwise a dispute could hold funds that never
+        // reached the account
+        let stored = match (outcome.is_applied(), referenced) {
+            (true, Some(referenced)) => Some(referenced),
+            (true, None) if tx.requires_history() => Some(*tx),
+            _ => None,
+        };
+
+        Ok(Change {
+            outcome,
+            claim: None,
+            account: Some(account),
+            stored,
+        })
+    }
+
+    /// Returns the reason `tx` is rejected before it reaches an account, if any, and claims its
+    /// tx id otherwise. This is all the engine a sharded run keeps on its reading thread.
+    pub(crate) fn screen(
+        &mut self,
+        line: u64,
+        tx: &Transaction,
+    ) -> Result<Option<TxRejection>, EngineError> {
+        let rejected = self.check(line, tx)?;
+
+        if rejected.is_none() && tx.requires_unique_tx() {
+            self.store
+                .insert_claim(tx.tx, Some(line))
+                .map_err(Into::into)?;
+        }
+
+        Ok(rejected)
+    }
+
+    /// Returns the reason `tx` is rejected before it reaches an account, if any. Every valid
+    /// deposit and withdrawal claims its tx id, whether or not it's later applied. Reusing a
+    /// claimed id is an error or a `DuplicateTx` rejection, depending on the policy, and leaves the
+    /// claim with the original tx.
+    fn check(&self, line: u64, tx: &Transaction) -> Result<Option<TxRejection>, EngineError> {
+        if !tx.valid_tx_data() {
+            return Ok(Some(TxRejection::InvalidData));
+        }
+
+        if tx.requires_unique_tx() && self.store.claimed(tx.tx).map_err(Into::into)? {
+            return match self.duplicates {
+                DuplicatePolicy::Abort => {
+                    Err(match self.claimed_on(tx.tx).map_err(Into::into)? {
+                        Some(first) => EngineError::DuplicateTx {
+                            tx: tx.tx,
+                            first,
+                            second: line,
+                        },
+                        None => EngineError::DuplicateRestoredTx {
+                            tx: tx.tx,
+                            second: line,
+                        },
+                    })
+                }
+                DuplicatePolicy::Skip | DuplicatePolicy::Report => {
+                    Ok(Some(TxRejection::DuplicateTx))
+                }
+            };
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the line the deposit or withdrawal that claimed `tx` was processed on, unless it
+    /// was claimed in an earlier run and restored from a snapshot.
+    pub fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, S::Error> {
+        self.store.claimed_on(tx)
+    }
+
+    /// Returns the rejected report row for `tx`, read from `line`, if it's reported at all.
+    /// Skipped duplicates aren't reported and reported ones name the line of the original, when it
+    /// was read in this run.
+    pub(crate) fn rejection(
+        &self,
+        line: u64,
+        tx: &Transaction,
+        reason: TxRejection,
+    ) -> Result<Option<RejectedTx>, S::Error> {
+        Ok(match (reason, self.duplicates) {
+            (TxRejection::DuplicateTx, DuplicatePolicy::Skip) => None,
+            (TxRejection::DuplicateTx, _) => {
+                Some(RejectedTx::duplicate(line, tx, self.claimed_on(tx.tx)?))
+            }
+            _ => Some(RejectedTx::new(line, tx, reason)),
+        })
+    }
+}
+
+/// What processing a transaction changes in an engine, worked out before anything is changed.
+#[derive(Clone, Debug)]
+pub(crate) struct Change {
+    pub(crate) outcome: TxOutcome,
+    /// The tx id the transaction claims, if it's a valid deposit or withdrawal.
+    claim: Option<TxId>,
+    /// The client's account as the transaction leaves it, unless it never reaches an account.
+    pub(crate) account: Option<Account>,
+    /// The deposit or withdrawal to store in the history as the transaction leaves it.
+    stored: Option<Transaction>,
+}
+
+impl Change {
+    fn rejected(reason: TxRejection) -> Self {
+        Self {
+            outcome: TxOutcome::Rejected(reason),
+            claim: None,
+            account: None,
+            stored: None,
+        }
+    }
+}
+
+/// What happens when a deposit or withdrawal reuses the tx id of an earlier one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Stop with `EngineError::DuplicateTx`.
+    #[default]
+    Abort,
+    /// Reject the duplicate as `duplicate_tx` and leave it out of the rejected report.
+    Skip,
+    /// Reject the duplicate as `duplicate_tx` and report it along with the line of the original.
+    Report,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            "report" => Ok(Self::Report),
+            _ => Err(format!(
+                "Unknown duplicate policy {s}, expected abort, skip or report"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::Timestamp;
+
+    /// Runs each scenario against a `MemoryStore` and against a `DiskStore` in a temporary
+    /// directory.
+    macro_rules! scenarios {
+        ($($name:ident),* $(,)?) => {
+            mod memory {
+                $(
+                    #[test]
+                    fn $name() {
+                        super::$name(crate::store::MemoryStore::default());
+                    }
+                )*
+            }
+
+            mod disk {
+                $(
+                    #[test]
+                    fn $name() {
+                        let dir = std::env::temp_dir().join(format!(
+                            "payments-engine-{}-{}",
+                            std::process::id(),
+                            stringify!($name)
+                        ));
+
+                        super::$name(crate::store::DiskStore::open(&dir).unwrap());
+                        std::fs::remove_dir_all(dir).unwrap();
+                    }
+                )*
+            }
+        };
+    }
+
+    scenarios!(
+        process_txs,
+        reject_cross_client_reference,
+        duplicate_deposit,
+        duplicate_withdrawal,
+        deposit_after_withdrawal,
+        skip_duplicate_deposit,
+        reject_invalid_data,
+        rejected_txs_are_not_referenceable,
+        rejected_txs_keep_their_id,
+        rejected_deposits_keep_their_id,
+        dispute_window,
+    );
+
+    fn account_of<S: LedgerStore>(engine: &Engine<S>, client: u16) -> Option<Account> {
+        engine
+            .store()
+            .account(ClientId(client))
+            .map_err(Into::<EngineError>::into)
+            .unwrap()
+    }
+
+    fn accounts_of<S: LedgerStore>(engine: &Engine<S>) -> Vec<Account> {
+        engine
+            .store()
+            .accounts()
+            .map_err(Into::<EngineError>::into)
+            .unwrap()
+    }
+
+    fn process_txs<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                2,
+                Some(dec!(3)),
+            ))
+            .unwrap();
+        engine
+            .process(Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .unwrap();
+
+        let account = account_of(&engine, 1).unwrap();
+
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(Decimal::ZERO, account.available);
+        assert_eq!(dec!(10), account.held);
+
+        let account = account_of(&engine, 2).unwrap();
+
+        assert_eq!(dec!(3), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        assert_eq!(None, account_of(&engine, 3));
+        assert_eq!(2, accounts_of(&engine).len());
+    }
+
+    fn reject_cross_client_reference<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        let outcome = engine
+            .process(Transaction::new(TransactionType::Dispute, 2, 1, None))
+            .unwrap();
+
+        assert_eq!(TxOutcome::Rejected(TxRejection::ClientMismatch), outcome);
+
+        let account = account_of(&engine, 1).unwrap();
+
+        assert_eq!(dec!(10), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
+    fn duplicate_deposit<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let deposit = || Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+
+        engine.process_line(2, deposit()).unwrap();
+
+        match engine.process_line(5, deposit()) {
+            Err(EngineError::DuplicateTx { tx, first, second }) => {
+                assert_eq!(TxId(1u32), tx);
+                assert_eq!(2, first);
+                assert_eq!(5, second);
+            }
+            other => panic!("expected a duplicate tx, got {other:?}"),
+        }
+    }
+
+    fn duplicate_withdrawal<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let withdrawal =
+            |client| Transaction::new(TransactionType::Withdrawal, client, 7, Some(dec!(1)));
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            TxOutcome::Applied,
+            engine.process(withdrawal(1u16)).unwrap()
+        );
+
+        for client in [1u16, 2] {
+            assert!(matches!(
+                engine.process(withdrawal(client)),
+                Err(EngineError::DuplicateTx {
+                    tx: TxId(7u32),
+                    first: 2,
+                    second: 3 | 4,
+                })
+            ));
+        }
+
+        assert_eq!(dec!(9), account_of(&engine, 1).unwrap().total);
+    }
+
+    fn deposit_after_withdrawal<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let tx = |_type| Transaction::new(_type, 1, 1, Some(dec!(10)));
+
+        // the withdrawal is rejected and never reaches the history, but still claims its id
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::InsufficientFunds),
+            engine.process(tx(TransactionType::Withdrawal)).unwrap()
+        );
+        assert!(matches!(
+            engine.process(tx(TransactionType::Deposit)),
+            Err(EngineError::DuplicateTx {
+                first: 1,
+                second: 2,
+                ..
+            })
+        ));
+        assert_eq!(Decimal::ZERO, account_of(&engine, 1).unwrap().total);
+    }
+
+    fn skip_duplicate_deposit<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Skip);
+        let deposit =
+            |client, amount| Transaction::new(TransactionType::Deposit, client, 1, Some(amount));
+
+        engine.process_line(2, deposit(1u16, dec!(10))).unwrap();
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::DuplicateTx),
+            engine.process_line(3, deposit(1u16, dec!(5))).unwrap()
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::DuplicateTx),
+            engine.process_line(4, deposit(2u16, dec!(5))).unwrap()
+        );
+        assert_eq!(
+            Some(2),
+            engine
+                .claimed_on(TxId(1u32))
+                .map_err(Into::<EngineError>::into)
+                .unwrap()
+        );
+        assert_eq!(dec!(10), account_of(&engine, 1).unwrap().total);
+        assert_eq!(None, account_of(&engine, 2));
+    }
+
+    fn reject_invalid_data<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+
+        let outcome = engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(-10)),
+            ))
+            .unwrap();
+
+        assert_eq!(TxOutcome::Rejected(TxRejection::InvalidData), outcome);
+        assert_eq!(None, account_of(&engine, 1));
+    }
+
+    fn rejected_txs_are_not_referenceable<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::ZERO),
+            ))
+            .unwrap();
+
+        let outcome = engine
+            .process(Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .unwrap();
+
+        assert_eq!(TxOutcome::Rejected(TxRejection::UnknownTx), outcome);
+    }
+
+    fn rejected_txs_keep_their_id<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 1, Some(dec!(10)));
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::InsufficientFunds),
+            engine.process(withdrawal).unwrap()
+        );
+
+        let outcome = engine
+            .process(Transaction::new(TransactionType::Dispute, 2, 1, None))
+            .unwrap();
+
+        // the withdrawal never reached the history, so there's no tx for the dispute to reference
+        assert_eq!(TxOutcome::Rejected(TxRejection::UnknownTx), outcome);
+        assert_eq!(None, account_of(&engine, 2));
+        assert!(matches!(
+            engine.process(withdrawal),
+            Err(EngineError::DuplicateTx {
+                first: 1,
+                second: 3,
+                ..
+            })
+        ));
+    }
+
+    fn rejected_deposits_keep_their_id<S: LedgerStore>(store: S) {
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let tx = |_type, client, tx, amount| Transaction::new(_type, client, tx, Some(amount));
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::ZeroAmount),
+            engine
+                .process(tx(TransactionType::Deposit, 1u16, 1u32, Decimal::ZERO))
+                .unwrap()
+        );
+
+        // lock client 2 through a charged back deposit
+        for _type in [
+            TransactionType::Deposit,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+        ] {
+            engine.process(tx(_type, 2u16, 2u32, dec!(5))).unwrap();
+        }
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AccountLocked),
+            engine
+                .process(tx(TransactionType::Deposit, 2u16, 3u32, dec!(5)))
+                .unwrap()
+        );
+
+        for (id, first, second) in [(1u32, 1, 6), (3, 5, 7)] {
+            let duplicate = engine.process(tx(TransactionType::Deposit, 3u16, id, dec!(1)));
+
+            assert!(
+                matches!(
+                    duplicate,
+                    Err(EngineError::DuplicateTx { tx, first: f, second: s })
+                        if (tx, f, s) == (TxId(id), first, second)
+                ),
+                "{duplicate:?}"
+            );
+        }
+
+        assert_eq!(None, account_of(&engine, 3));
+    }
+
+    fn dispute_window<S: LedgerStore>(store: S) {
+        let mut engine =
+            Engine::with_store(store, DuplicatePolicy::Abort).with_dispute_window(Some(60));
+        let tx = |_type, tx, timestamp: Option<u32>| Transaction {
+            timestamp: timestamp.map(Timestamp),
+            ..Transaction::new(_type, 1, tx, None)
+        };
+
+        for (id, timestamp) in [(1u32, Some(100)), (2, Some(100)), (3, None)] {
+            engine
+                .process(Transaction {
+                    amount: Some(dec!(1)),
+                    ..tx(TransactionType::Deposit, id, timestamp)
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            TxOutcome::Applied,
+            engine
+                .process(tx(TransactionType::Dispute, 1, Some(160)))
+                .unwrap()
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::DisputeWindowExpired),
+            engine
+                .process(tx(TransactionType::Dispute, 2, Some(161)))
+                .unwrap()
+        );
+
+        // without both timestamps there's no telling how late a dispute is
+        assert_eq!(
+            TxOutcome::Applied,
+            engine
+                .process(tx(TransactionType::Dispute, 2, None))
+                .unwrap()
+        );
+        assert_eq!(
+            TxOutcome::Applied,
+            engine
+                .process(tx(TransactionType::Dispute, 3, Some(u32::MAX)))
+                .unwrap()
+        );
+
+        // resolves and chargebacks aren't limited by the window
+        assert_eq!(
+            TxOutcome::Applied,
+            engine
+                .process(tx(TransactionType::Chargeback, 1, Some(u32::MAX)))
+                .unwrap()
+        );
+
+        let account = account_of(&engine, 1).unwrap();
+
+        assert_eq!(dec!(2), account.held);
+        assert_eq!(dec!(2), account.total);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn sorted_accounts() {
+        let mut engine = Engine::new();
+
+        for (client, tx, amount) in [(3u16, 1u32, dec!(5)), (1, 2, dec!(1)), (2, 3, dec!(5))] {
+            engine
+                .process(Transaction::new(
+                    TransactionType::Deposit,
+                    client,
+                    tx,
+                    Some(amount),
+                ))
+                .unwrap();
+        }
+
+        let clients = |sort| {
+            engine
+                .sorted_accounts(sort)
+                .into_iter()
+                .map(|account| account.client.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![1, 2, 3], clients(SortBy::Client));
+        assert_eq!(vec![2, 3, 1], clients(SortBy::Total));
+        assert_eq!(vec![2, 3, 1], clients(SortBy::Available));
+    }
+}
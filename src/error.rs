@@ -0,0 +1,103 @@
+use std::{error, fmt, io};
+
+use crate::transaction::TxId;
+
+/// Everything that can stop a run. Rejected transactions aren't errors, they're reported as a
+/// `TxOutcome` instead.
+#[derive(Debug)]
+pub enum EngineError {
+    /// A record that can't be read as a transaction. `column` names the offending field when it's
+    /// known.
+    Parse {
+        line: u64,
+        column: Option<String>,
+        message: String,
+    },
+    /// A deposit or withdrawal reused the tx id of an earlier one. `first` and `second` are the
+    /// lines the two occurrences were read from.
+    DuplicateTx { tx: TxId, first: u64, second: u64 },
+    /// A deposit or withdrawal reused the tx id of one from an earlier run, restored from a
+    /// snapshot. `second` is the line the reuse was read from.
+    DuplicateRestoredTx { tx: TxId, second: u64 },
+    /// Reading the transactions or writing the accounts or report failed.
+    Io(io::Error),
+    /// A snapshot couldn't be loaded, because it's malformed or from another version.
+    Snapshot { message: String },
+    /// A journal couldn't be replayed, because it's malformed, from another version or doesn't
+    /// match the engine it's replayed into.
+    Journal { message: String },
+    /// The command line arguments couldn't be parsed. `usage` describes the accepted arguments.
+    Usage {
+        message: String,
+        usage: &'static str,
+    },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse {
+                line,
+                column: Some(column),
+                message,
+            } => write!(f, "line {line}, column {column}: {message}"),
+            Self::Parse {
+                line,
+                column: None,
+                message,
+            } => write!(f, "line {line}: {message}"),
+            Self::DuplicateTx { tx, first, second } => write!(
+                f,
+                "line {second}: tx {} was already used on line {first}, withdrawal and deposit \
+                 txs must be globally unique",
+                tx.0
+            ),
+            Self::DuplicateRestoredTx { tx, second } => write!(
+                f,
+                "line {second}: tx {} was already used in an earlier run, withdrawal and deposit \
+                 txs must be globally unique",
+                tx.0
+            ),
+            Self::Io(err) => err.fmt(f),
+            Self::Snapshot { message } => write!(f, "snapshot: {message}"),
+            Self::Journal { message } => write!(f, "journal: {message}"),
+            Self::Usage { message, usage } => write!(f, "{message}\n{usage}"),
+        }
+    }
+}
+
+impl error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Parse { .. }
+            | Self::DuplicateTx { .. }
+            | Self::DuplicateRestoredTx { .. }
+            | Self::Snapshot { .. }
+            | Self::Journal { .. }
+            | Self::Usage { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map_or(0, |position| position.line());
+        let message = err.to_string();
+
+        match err.into_kind() {
+            csv::ErrorKind::Io(err) => Self::Io(err),
+            _ => Self::Parse {
+                line,
+                column: None,
+                message,
+            },
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use std::{io, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::{error::EngineError, transaction::TransactionType};
+
+const USAGE: &str = "Usage: gen [--transactions <n>] [--clients <n>] [--seed <n>] \
+                     [--deposits <weight>] [--withdrawals <weight>] [--disputes <weight>] \
+                     [--resolves <weight>] [--chargebacks <weight>] [--malformed <rate>] \
+                     [--negative <rate>] [--excess-precision <rate>]";
+
+/// The relative weight of each transaction type in a generated stream. Disputes only reference
+/// earlier deposits and withdrawals, and resolves and chargebacks only reference active disputes,
+/// so a type falls back to a deposit while there's nothing for it to reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub dispute: u32,
+    pub resolve: u32,
+    pub chargeback: u32,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Self {
+            deposit: 60,
+            withdrawal: 30,
+            dispute: 6,
+            resolve: 3,
+            chargeback: 1,
+        }
+    }
+}
+
+impl Mix {
+    fn pick(&self, rng: &mut Rng) -> TransactionType {
+        let weights = [
+            (TransactionType::Deposit, self.deposit),
+            (TransactionType::Withdrawal, self.withdrawal),
+            (TransactionType::Dispute, self.dispute),
+            (TransactionType::Resolve, self.resolve),
+            (TransactionType::Chargeback, self.chargeback),
+        ];
+        let total = weights.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        let mut roll = rng.below(total);
+
+        for (_type, weight) in weights {
+            if roll < u64::from(weight) {
+                return _type;
+            }
+
+            roll -= u64::from(weight);
+        }
+
+        TransactionType::Deposit
+    }
+}
+
+/// The chance, from `0.0` to `1.0`, that a generated row is deliberately broken. Negative and
+/// excess precision amounts only apply to deposits and withdrawals. They still parse and are
+/// rejected by `Transaction::valid_tx_data`, while a malformed row can't be parsed at all, so the
+/// engine stops at the first one. Use `malformed` to exercise parse errors, not rejections.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults {
+    pub malformed: f64,
+    pub negative: f64,
+    pub excess_precision: f64,
+}
+
+impl Faults {
+    /// Returns a corrupted copy of `amount`, if this row is one of the unlucky ones.
+    fn corrupt(&self, rng: &mut Rng, amount: Decimal) -> Option<Decimal> {
+        if rng.chance(self.negative) {
+            Some(-amount)
+        } else if rng.chance(self.excess_precision) {
+            // a non zero fifth place, one more than an amount may have
+            let fifth = i128::from(rng.below(9) + 1);
+
+            Some(Decimal::from_i128_with_scale(
+                amount.mantissa() * 10 + fifth,
+                5,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Generates a synthetic, reproducible stream of transactions in the CSV input format. The same
+/// settings always produce the same stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Generator {
+    /// The number of rows written, not counting the header. Every row may need a tx id of its own,
+    /// so there are at most as many rows as there are ids.
+    pub transactions: u32,
+    /// Clients are drawn uniformly from `1..=clients`.
+    pub clients: u16,
+    pub mix: Mix,
+    pub faults: Faults,
+    pub seed: u64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            transactions: 10_000,
+            clients: 1_000,
+            mix: Mix::default(),
+            faults: Faults::default(),
+            seed: 0,
+        }
+    }
+}
+
+impl Generator {
+    /// Parses the arguments that follow the program name. Anything not given keeps its default.
+    pub fn from_args<I>(args: I) -> Result<Self, EngineError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut generator = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let flag = arg.as_str();
+            let value = args.next();
+
+            match flag {
+                "--transactions" => {
+                    let transactions: u64 = parse(flag, value)?;
+
+                    generator.transactions = u32::try_from(transactions).map_err(|_| {
+                        usage_error(format!("{flag} can't be more than {}", u32::MAX))
+                    })?;
+                }
+                "--clients" => generator.clients = parse(flag, value)?,
+                "--seed" => generator.seed = parse(flag, value)?,
+                "--deposits" => generator.mix.deposit = parse(flag, value)?,
+                "--withdrawals" => generator.mix.withdrawal = parse(flag, value)?,
+                "--disputes" => generator.mix.dispute = parse(flag, value)?,
+                "--resolves" => generator.mix.resolve = parse(flag, value)?,
+                "--chargebacks" => generator.mix.chargeback = parse(flag, value)?,
+                "--malformed" => generator.faults.malformed = rate(flag, value)?,
+                "--negative" => generator.faults.negative = rate(flag, value)?,
+                "--excess-precision" => generator.faults.excess_precision = rate(flag, value)?,
+                _ => return Err(usage_error(format!("Unexpected argument {arg}"))),
+            }
+        }
+
+        if generator.clients == 0 {
+            return Err(usage_error("--clients must be at least 1"));
+        }
+
+        Ok(generator)
+    }
+
+    /// Writes the header followed by `transactions` rows to `output`.
+    pub fn write_csv<W: io::Write>(&self, output: W) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(output);
+        let mut rng = Rng(self.seed);
+        // deposits and withdrawals that can be disputed, and the disputes that are active
+        let mut disputable = Vec::new();
+        let mut disputed = Vec::new();
+        let mut next_tx = 1u32;
+
+        writer.write_record(["type", "client", "tx", "amount"])?;
+
+        for _ in 0..self.transactions {
+            if rng.chance(self.faults.malformed) {
+                let client = self.client(&mut rng);
+
+                writer.write_record(malformed(&mut rng, client, next_tx))?;
+
+                continue;
+            }
+
+            let (_type, client, tx, amount) = match self.mix.pick(&mut rng) {
+                TransactionType::Dispute if !disputable.is_empty() => {
+                    let (client, tx) = take(&mut disputable, &mut rng);
+
+                    disputed.push((client, tx));
+
+                    (TransactionType::Dispute, client, tx, None)
+                }
+                TransactionType::Resolve if !disputed.is_empty() => {
+                    let (client, tx) = take(&mut disputed, &mut rng);
+
+                    disputable.push((client, tx));
+
+                    (TransactionType::Resolve, client, tx, None)
+                }
+                TransactionType::Chargeback if !disputed.is_empty() => {
+                    let (client, tx) = take(&mut disputed, &mut rng);
+
+                    (TransactionType::Chargeback, client, tx, None)
+                }
+                picked => {
+                    let (_type, max) = match picked {
+                        TransactionType::Withdrawal => (TransactionType::Withdrawal, 100),
+                        _ => (TransactionType::Deposit, 1_000),
+                    };
+                    let client = self.client(&mut rng);
+                    let tx = next_tx;
+                    let amount = amount(&mut rng, max);
+
+                    // only the last of `u32::MAX` rows could overflow, and it needs no id after it
+                    next_tx = next_tx.saturating_add(1);
+
+                    // invalid amounts are rejected, so nothing can reference them
+                    let amount = self.faults.corrupt(&mut rng, amount).unwrap_or_else(|| {
+                        disputable.push((client, tx));
+
+                        amount
+                    });
+
+                    (_type, client, tx, Some(amount))
+                }
+            };
+
+            writer.write_record([
+                name(_type),
+                &client.to_string(),
+                &tx.to_string(),
+                &amount.map_or_else(String::new, |amount| amount.to_string()),
+            ])?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn client(&self, rng: &mut Rng) -> u16 {
+        // the cast can't truncate as the result is below `clients`
+        rng.below(u64::from(self.clients.max(1))) as u16 + 1
+    }
+}
+
+fn parse<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, EngineError> {
+    let value = value.ok_or_else(|| usage_error(format!("{flag} requires a value")))?;
+
+    value
+        .parse()
+        .map_err(|_| usage_error(format!("{flag} got an invalid value {value}")))
+}
+
+fn rate(flag: &str, value: Option<String>) -> Result<f64, EngineError> {
+    let rate = parse(flag, value)?;
+
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(usage_error(format!("{flag} must be between 0 and 1")))
+    }
+}
+
+fn usage_error<S: Into<String>>(message: S) -> EngineError {
+    EngineError::Usage {
+        message: message.into(),
+        usage: USAGE,
+    }
+}
+
+fn name(_type: TransactionType) -> &'static str {
+    match _type {
+        TransactionType::Chargeback => "chargeback",
+        TransactionType::Deposit => "deposit",
+        TransactionType::Dispute => "dispute",
+        TransactionType::Resolve => "resolve",
+        TransactionType::Withdrawal => "withdrawal",
+    }
+}
+
+/// Returns a row that can't be parsed as a transaction.
+fn malformed(rng: &mut Rng, client: u16, tx: u32) -> [String; 4] {
+    let (client, tx) = (client.to_string(), tx.to_string());
+
+    match rng.below(4) {
+        0 => ["refund".into(), client, tx, "1.0".into()],
+        1 => ["deposit".into(), "client".into(), tx, "1.0".into()],
+        2 => ["deposit".into(), client, "-1".into(), "1.0".into()],
+        _ => ["deposit".into(), client, tx, "1.2asdf".into()],
+    }
+}
+
+/// Returns an amount with four decimal places between `0.0001` and `max`.
+fn amount(rng: &mut Rng, max: u32) -> Decimal {
+    let units = rng.below(u64::from(max) * 10_000) + 1;
+
+    // a `u32` worth of whole units always fits in an `i64` of ten thousandths
+    Decimal::new(units as i64, 4)
+}
+
+/// Removes a random element of `txs`, which must not be empty.
+fn take(txs: &mut Vec<(u16, u32)>, rng: &mut Rng) -> (u16, u32) {
+    // the cast can't truncate as the result is below a `usize`
+    let index = rng.below(txs.len() as u64) as usize;
+
+    txs.swap_remove(index)
+}
+
+/// A splitmix64 generator. It's fast, good enough for test data and, unlike the thread local
+/// generators, reproducible from a seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// Returns `true` with a probability of `chance`.
+    fn chance(&mut self, chance: f64) -> bool {
+        // nothing is drawn for a disabled fault, so enabling none leaves the stream unchanged, and
+        // the top 53 bits are exactly representable as an `f64` in `0.0..1.0`
+        chance > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < chance
+    }
+
+    /// Returns a number in `0..bound`, or zero when `bound` is zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            0
+        } else {
+            self.next() % bound
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{outcome::TxOutcome, Engine};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn generate(generator: Generator) -> Vec<u8> {
+        let mut csv = Vec::new();
+
+        generator.write_csv(&mut csv).unwrap();
+
+        csv
+    }
+
+    #[test]
+    fn from_args() {
+        assert_eq!(
+            Generator::default(),
+            Generator::from_args(args(&[])).unwrap()
+        );
+
+        let actual = Generator::from_args(args(&[
+            "--transactions",
+            "1000000",
+            "--clients",
+            "65535",
+            "--seed",
+            "42",
+            "--deposits",
+            "3",
+            "--withdrawals",
+            "1",
+            "--disputes",
+            "0",
+            "--resolves",
+            "0",
+            "--chargebacks",
+            "0",
+            "--malformed",
+            "0.001",
+            "--negative",
+            "0.01",
+            "--excess-precision",
+            "0.5",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            Generator {
+                transactions: 1_000_000,
+                clients: u16::MAX,
+                mix: Mix {
+                    deposit: 3,
+                    withdrawal: 1,
+                    dispute: 0,
+                    resolve: 0,
+                    chargeback: 0,
+                },
+                faults: Faults {
+                    malformed: 0.001,
+                    negative: 0.01,
+                    excess_precision: 0.5,
+                },
+                seed: 42,
+            },
+            actual
+        );
+    }
+
+    #[test]
+    fn invalid_args() {
+        assert!(Generator::from_args(args(&["--clients", "0"])).is_err());
+        assert!(Generator::from_args(args(&["--clients", "65536"])).is_err());
+        assert!(Generator::from_args(args(&["--transactions"])).is_err());
+        assert!(Generator::from_args(args(&["--transactions", "4294967296"])).is_err());
+        assert!(Generator::from_args(args(&["--deposits", "-1"])).is_err());
+        assert!(Generator::from_args(args(&["--negative", "1.5"])).is_err());
+        assert!(Generator::from_args(args(&["--malformed", "often"])).is_err());
+        assert!(Generator::from_args(args(&["out.csv"])).is_err());
+    }
+
+    #[test]
+    fn reproducible() {
+        let generator = Generator {
+            seed: 7,
+            ..Default::default()
+        };
+
+        assert_eq!(generate(generator), generate(generator));
+        assert_ne!(
+            generate(generator),
+            generate(Generator {
+                seed: 8,
+                ..generator
+            })
+        );
+    }
+
+    #[test]
+    fn valid_stream() {
+        let csv = generate(Generator {
+            transactions: 5_000,
+            clients: 10,
+            mix: Mix {
+                deposit: 40,
+                withdrawal: 10,
+                dispute: 30,
+                resolve: 15,
+                chargeback: 5,
+            },
+            faults: Faults::default(),
+            seed: 1,
+        });
+        let mut engine = Engine::new();
+        let mut rows = 0;
+        let mut applied = [0; 5];
+
+        crate::input::read_txs(&csv[..], crate::input::InputFormat::Csv, |_, tx| {
+            rows += 1;
+
+            if engine.process(tx)? == TxOutcome::Applied {
+                applied[tx._type as usize] += 1;
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(5_000, rows);
+        assert!(applied.iter().all(|applied| *applied > 0), "{applied:?}");
+        assert!(engine
+            .accounts()
+            .all(|account| (1..=10).contains(&account.client.0)));
+    }
+
+    #[test]
+    fn invalid_amounts() {
+        let csv = generate(Generator {
+            faults: Faults {
+                negative: 0.1,
+                excess_precision: 0.1,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let mut engine = Engine::new();
+        let mut negative = 0;
+        let mut excess_precision = 0;
+
+        crate::input::read_txs(&csv[..], crate::input::InputFormat::Csv, |_, tx| {
+            let amount = tx.amount.unwrap_or_default();
+
+            if amount.is_sign_negative() {
+                negative += 1;
+            } else if amount.scale() > 4 {
+                excess_precision += 1;
+            }
+
+            let outcome = engine.process(tx)?;
+
+            if !tx.valid_tx_data() {
+                assert_eq!(
+                    TxOutcome::Rejected(crate::outcome::TxRejection::InvalidData),
+                    outcome
+                );
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(negative > 500, "{negative}");
+        assert!(excess_precision > 500, "{excess_precision}");
+    }
+
+    #[test]
+    fn malformed_rows() {
+        let generator = Generator {
+            transactions: 1_000,
+            faults: Faults {
+                malformed: 0.05,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let csv = String::from_utf8(generate(generator)).unwrap();
+        let malformed = csv
+            .lines()
+            .skip(1)
+            .filter(|row| {
+                crate::input::read_txs(
+                    format!("type,client,tx,amount\n{row}\n").as_bytes(),
+                    crate::input::InputFormat::Csv,
+                    |_, _| Ok(()),
+                )
+                .is_err()
+            })
+            .count();
+
+        assert_eq!(1_001, csv.lines().count());
+        assert!((20..80).contains(&malformed), "{malformed}");
+    }
+}
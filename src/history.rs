@@ -0,0 +1,235 @@
+use std::{collections::HashMap, iter};
+
+use crate::transaction::{HistoryRecord, TxId};
+
+/// Ids per page, the ones sharing all but their lowest six bits.
+const PAGE_IDS: u32 = 64;
+/// The most records a page keeps as a sorted list. Past this a list is larger than the page's
+/// array of records, so it's switched over.
+const LIST_LEN: usize = PAGE_IDS as usize / 2;
+
+/// The records of a page, by the lowest six bits of their tx id.
+#[derive(Debug)]
+enum Page {
+    List(Vec<(u8, HistoryRecord)>),
+    Array(Box<[Option<HistoryRecord>; PAGE_IDS as usize]>),
+}
+
+/// The deposits and withdrawals disputes can reference, in small pages of consecutive tx ids kept
+/// in a map by page. As with `SeenTxs`, each page starts out as a sorted list and becomes an array
+/// indexed by tx id once it fills up. Ids handed out in order cost about a record each, without a
+/// stored key or a hash table entry per record, while scattered ids cost a record, a byte and a
+/// map entry each rather than a mostly empty page.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    pages: HashMap<u32, Page>,
+}
+
+impl History {
+    pub(crate) fn get(&self, tx: TxId) -> Option<&HistoryRecord> {
+        let (page, slot) = Self::locate(tx);
+
+        match self.pages.get(&page)? {
+            Page::List(records) => {
+                let index = records
+                    .binary_search_by_key(&slot, |(slot, _)| *slot)
+                    .ok()?;
+
+                Some(&records[index].1)
+            }
+            Page::Array(records) => records[usize::from(slot)].as_ref(),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, tx: TxId) -> Option<&mut HistoryRecord> {
+        let (page, slot) = Self::locate(tx);
+
+        match self.pages.get_mut(&page)? {
+            Page::List(records) => {
+                let index = records
+                    .binary_search_by_key(&slot, |(slot, _)| *slot)
+                    .ok()?;
+
+                Some(&mut records[index].1)
+            }
+            Page::Array(records) => records[usize::from(slot)].as_mut(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, tx: TxId, record: HistoryRecord) {
+        let (page, slot) = Self::locate(tx);
+        let page = self
+            .pages
+            .entry(page)
+            // scattered ids mostly leave a page with a single record, so there's no room for more
+            // until a second one turns up
+            .or_insert_with(|| Page::List(Vec::with_capacity(1)));
+
+        if let Page::List(records) = page {
+            match records.binary_search_by_key(&slot, |(slot, _)| *slot) {
+                Ok(index) => {
+                    records[index].1 = record;
+
+                    return;
+                }
+                Err(index) if records.len() < LIST_LEN => {
+                    records.insert(index, (slot, record));
+
+                    return;
+                }
+                Err(_) => {}
+            }
+
+            let mut array = Self::array();
+
+            for (slot, record) in records.drain(..) {
+                array[usize::from(slot)] = Some(record);
+            }
+
+            *page = Page::Array(array);
+        }
+
+        let Page::Array(records) = page else {
+            unreachable!("lists are either inserted into or switched to an array above");
+        };
+
+        records[usize::from(slot)] = Some(record);
+    }
+
+    /// Returns every record along with its tx id, in tx id order. Only the page numbers are
+    /// sorted, which takes a fraction of the memory the records do.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (TxId, &HistoryRecord)> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+
+        pages.sort_unstable_by_key(|(page, _)| **page);
+
+        pages.into_iter().flat_map(|(page, records)| {
+            let records: Box<dyn Iterator<Item = (u8, &HistoryRecord)>> = match records {
+                Page::List(records) => {
+                    Box::new(records.iter().map(|(slot, record)| (*slot, record)))
+                }
+                Page::Array(records) => Box::new(
+                    records
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(slot, record)| Some((slot as u8, record.as_ref()?))),
+                ),
+            };
+
+            records.map(move |(slot, record)| (Self::tx(*page, slot), record))
+        })
+    }
+
+    /// Hands over every record along with its tx id, in no particular order.
+    pub(crate) fn into_records(self) -> impl Iterator<Item = (TxId, HistoryRecord)> {
+        self.pages.into_iter().flat_map(|(page, records)| {
+            let records: Box<dyn Iterator<Item = (u8, HistoryRecord)>> = match records {
+                Page::List(records) => Box::new(records.into_iter()),
+                Page::Array(records) => Box::new(
+                    Vec::from(records as Box<[_]>)
+                        .into_iter()
+                        .enumerate()
+                        .filter_map(|(slot, record)| Some((slot as u8, record?))),
+                ),
+            };
+
+            records.map(move |(slot, record)| (Self::tx(page, slot), record))
+        })
+    }
+
+    /// Allocates an empty array of records on the heap, without building it on the stack first.
+    fn array() -> Box<[Option<HistoryRecord>; PAGE_IDS as usize]> {
+        let records: Box<[_]> = iter::repeat_with(|| None).take(PAGE_IDS as usize).collect();
+
+        records.try_into().expect("PAGE_IDS records")
+    }
+
+    fn locate(tx: TxId) -> (u32, u8) {
+        // the remainder is always below 64
+        (tx.0 / PAGE_IDS, (tx.0 % PAGE_IDS) as u8)
+    }
+
+    fn tx(page: u32, slot: u8) -> TxId {
+        TxId(page * PAGE_IDS + u32::from(slot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::{ClientId, RecordKind, TransactionState};
+
+    fn record(client: u16) -> HistoryRecord {
+        HistoryRecord {
+            amount: dec!(1),
+            disputes: None,
+            client: ClientId(client),
+            kind: RecordKind::Deposit,
+            state: TransactionState::Open,
+            seconds: 0,
+        }
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut history = History::default();
+        let txs = [0u32, 1, 63, 64, 16_384, 1_000_000, u32::MAX];
+
+        for tx in txs {
+            assert_eq!(None, history.get(TxId(tx)), "{tx}");
+
+            history.insert(TxId(tx), record(1));
+
+            assert_eq!(Some(&record(1)), history.get(TxId(tx)), "{tx}");
+        }
+
+        history.get_mut(TxId(64u32)).unwrap().state = TransactionState::ActiveDispute;
+        history.insert(TxId(1u32), record(2));
+
+        assert_eq!(
+            TransactionState::ActiveDispute,
+            history.get(TxId(64u32)).unwrap().state
+        );
+        assert_eq!(Some(&record(2)), history.get(TxId(1u32)));
+        assert_eq!(None, history.get(TxId(2u32)));
+        assert_eq!(None, history.get_mut(TxId(u32::MAX - 1)));
+
+        // only the pages holding a record are allocated
+        assert_eq!(5, history.pages.len());
+
+        let ids: Vec<_> = history.iter().map(|(tx, _)| tx.0).collect();
+
+        assert_eq!(txs.to_vec(), ids);
+
+        let mut ids: Vec<_> = history.into_records().map(|(tx, _)| tx.0).collect();
+
+        ids.sort_unstable();
+
+        assert_eq!(txs.to_vec(), ids);
+    }
+
+    #[test]
+    fn list_becomes_array() {
+        let mut history = History::default();
+        // every id of the second page, in reverse, so the list is inserted into at the front
+        let txs: Vec<_> = (PAGE_IDS..2 * PAGE_IDS).rev().collect();
+
+        for (inserted, tx) in txs.iter().enumerate() {
+            history.insert(TxId(*tx), record((*tx % 3) as u16));
+
+            let list = matches!(history.pages[&1], Page::List(_));
+
+            assert_eq!(inserted < LIST_LEN, list, "{tx}");
+        }
+
+        for tx in &txs {
+            assert_eq!(Some(&record((*tx % 3) as u16)), history.get(TxId(*tx)));
+        }
+
+        let ids: Vec<_> = history.iter().map(|(tx, _)| tx.0).collect();
+
+        assert_eq!((PAGE_IDS..2 * PAGE_IDS).collect::<Vec<_>>(), ids);
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    io::{self, BufRead},
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+use serde::{de::Unexpected, Deserialize};
+use serde_json::value::RawValue;
+
+use crate::{
+    de,
+    error::EngineError,
+    transaction::{
+        ClientId, DisputedAmounts, Timestamp, Transaction, TransactionState, TransactionType, TxId,
+    },
+};
+
+/// The format transactions are read in. Both formats share the `type`, `client`, `tx`, `amount`
+/// and `timestamp` fields.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// Newline delimited JSON, one transaction object per line.
+    Jsonl,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => Err(format!("Unknown input format {s}, expected csv or jsonl")),
+        }
+    }
+}
+
+/// Reads every transaction from `input`, calling `f` with each transaction and the line it was
+/// read from. Reading stops at the first record that can't be deserialized.
+pub fn read_txs<R, F>(input: R, format: InputFormat, f: F) -> Result<(), EngineError>
+where
+    R: io::Read,
+    F: FnMut(u64, Transaction) -> Result<(), EngineError>,
+{
+    match format {
+        InputFormat::Csv => read_csv(input, f),
+        InputFormat::Jsonl => read_jsonl(input, f),
+    }
+}
+
+fn read_csv<R, F>(input: R, mut f: F) -> Result<(), EngineError>
+where
+    R: io::Read,
+    F: FnMut(u64, Transaction) -> Result<(), EngineError>,
+{
+    // fields are trimmed while they're parsed, which is much cheaper than `csv::Trim::All`
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::Headers)
+        .from_reader(input);
+    let columns = CsvColumns::new(reader.byte_headers()?)?;
+    let mut record = csv::ByteRecord::new();
+
+    while reader.read_byte_record(&mut record)? {
+        let line = record.position().map_or(0, |position| position.line());
+        let tx = columns.parse(&record, line)?;
+
+        f(line, tx)?;
+    }
+
+    Ok(())
+}
+
+/// The index of each transaction field within a CSV record, found from the headers so columns
+/// can appear in any order. The `amount` and `timestamp` columns may be left out entirely.
+struct CsvColumns {
+    _type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl CsvColumns {
+    fn new(headers: &csv::ByteRecord) -> Result<Self, EngineError> {
+        let position = |name: &str| headers.iter().position(|header| header == name.as_bytes());
+        let required = |name: &str| {
+            position(name).ok_or_else(|| EngineError::Parse {
+                line: 1,
+                column: None,
+                message: format!("missing column {name}"),
+            })
+        };
+
+        Ok(Self {
+            _type: required("type")?,
+            client: required("client")?,
+            tx: required("tx")?,
+            amount: position("amount"),
+            timestamp: position("timestamp"),
+        })
+    }
+
+    fn parse(&self, record: &csv::ByteRecord, line: u64) -> Result<Transaction, EngineError> {
+        let amount = match self.amount {
+            Some(index) => parse_field(record, line, index, "amount", de::parse_amount)?,
+            None => None,
+        };
+        let timestamp = match self.timestamp {
+            Some(index) => parse_field(record, line, index, "timestamp", de::parse_timestamp)?,
+            None => None,
+        };
+
+        Ok(Transaction {
+            _type: parse_field(record, line, self._type, "type", de::parse_tx_type)?,
+            client: ClientId(parse_field(
+                record,
+                line,
+                self.client,
+                "client",
+                de::parse_id,
+            )?),
+            tx: TxId(parse_field(record, line, self.tx, "tx", de::parse_id)?),
+            amount,
+            state: TransactionState::Open,
+            disputes: DisputedAmounts::default(),
+            timestamp,
+        })
+    }
+}
+
+fn parse_field<T, P>(
+    record: &csv::ByteRecord,
+    line: u64,
+    index: usize,
+    name: &str,
+    parse: P,
+) -> Result<T, EngineError>
+where
+    P: FnOnce(&[u8]) -> Option<T>,
+{
+    let value = record.get(index).unwrap_or_default();
+
+    parse(value).ok_or_else(|| EngineError::Parse {
+        line,
+        column: Some(name.to_string()),
+        message: format!("invalid value {:?}", String::from_utf8_lossy(value)),
+    })
+}
+
+fn read_jsonl<R, F>(input: R, mut f: F) -> Result<(), EngineError>
+where
+    R: io::Read,
+    F: FnMut(u64, Transaction) -> Result<(), EngineError>,
+{
+    for (line, record) in (1..).zip(io::BufReader::new(input).lines()) {
+        let record = record?;
+
+        // blank lines, including a trailing one, separate nothing
+        if record.trim().is_empty() {
+            continue;
+        }
+
+        let tx: JsonTx = serde_json::from_str(&record).map_err(|err| EngineError::Parse {
+            line,
+            column: None,
+            message: err.to_string(),
+        })?;
+        let amount = json_amount(tx.amount).map_err(|err| EngineError::Parse {
+            line,
+            column: Some("amount".to_string()),
+            message: err.to_string(),
+        })?;
+
+        f(
+            line,
+            Transaction {
+                _type: tx._type,
+                client: tx.client,
+                tx: tx.tx,
+                amount,
+                state: TransactionState::Open,
+                disputes: DisputedAmounts::default(),
+                timestamp: tx.timestamp,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A JSON line, with the amount kept as the token it was written as.
+#[derive(Deserialize)]
+struct JsonTx<'a> {
+    #[serde(rename = "type")]
+    _type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    #[serde(default, borrow)]
+    amount: Option<&'a RawValue>,
+    #[serde(default, deserialize_with = "de::timestamp")]
+    timestamp: Option<Timestamp>,
+}
+
+/// Parses a numeric amount from its text, since going through an `f64` rounds anything past 15
+/// significant digits. Strings and `null` go through `de::amount` like any other field.
+fn json_amount(amount: Option<&RawValue>) -> Result<Option<Decimal>, serde_json::Error> {
+    match amount.map(RawValue::get) {
+        Some(token) if token.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+            de::parse_decimal(token.as_bytes())
+                .map(Some)
+                .ok_or_else(|| {
+                    serde::de::Error::invalid_value(Unexpected::Other(token), &"a decimal amount")
+                })
+        }
+        Some(token) => de::amount(&mut serde_json::Deserializer::from_str(token)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn collect(input: &str, format: InputFormat) -> Result<Vec<(u64, Transaction)>, String> {
+        let mut accum = Vec::default();
+
+        read_txs(input.as_bytes(), format, |line, tx| {
+            accum.push((line, tx));
+
+            Ok(())
+        })
+        .map_err(|err| err.to_string())?;
+
+        Ok(accum)
+    }
+
+    #[test]
+    fn jsonl_des() {
+        let actual = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}
+{"type":"withdrawal","client":2,"tx":2,"amount":"2.0001"}
+
+{"type": "dispute", "client": 1, "tx": 1}
+"#;
+
+        assert_eq!(
+            Ok(vec![
+                (
+                    1,
+                    Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(1.5)))
+                ),
+                (
+                    2,
+                    Transaction::new(TransactionType::Withdrawal, 2, 2, Some(dec!(2.0001)))
+                ),
+                (4, Transaction::new(TransactionType::Dispute, 1, 1, None)),
+            ]),
+            collect(actual, InputFormat::Jsonl)
+        );
+    }
+
+    #[test]
+    fn jsonl_exact_amounts() {
+        let actual = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 12345678901234.5678}
+{"type": "deposit", "client": 1, "tx": 2, "amount": "12345678901234.5678"}
+{"type": "deposit", "client": 1, "tx": 3, "amount": null}
+"#;
+        let amounts: Vec<_> = collect(actual, InputFormat::Jsonl)
+            .unwrap()
+            .into_iter()
+            .map(|(_, tx)| tx.amount)
+            .collect();
+
+        assert_eq!(
+            vec![
+                Some(dec!(12345678901234.5678)),
+                Some(dec!(12345678901234.5678)),
+                None
+            ],
+            amounts
+        );
+
+        let err = collect(
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1e3}"#,
+            InputFormat::Jsonl,
+        )
+        .unwrap_err();
+
+        assert!(err.starts_with("line 1, column amount:"), "{err}");
+    }
+
+    #[test]
+    fn jsonl_parse_error() {
+        let actual = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}
+{"type": "refund", "client": 1, "tx": 2, "amount": 1.5}
+"#;
+        let err = collect(actual, InputFormat::Jsonl).unwrap_err();
+
+        assert!(err.starts_with("line 2:"), "{err}");
+    }
+
+    #[test]
+    fn csv_lines() {
+        let actual = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 0.5\n";
+        let lines: Vec<_> = collect(actual, InputFormat::Csv)
+            .unwrap()
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect();
+
+        assert_eq!(vec![2, 3], lines);
+    }
+
+    #[test]
+    fn csv_des() {
+        let actual = "type,client,tx,amount\n\
+                      deposit,1,1,1.2\n\
+                      deposit,    1,    2,    1.2\n\
+                      \tdeposit\t,\t1\t,\t3\t,    1.2    \n\
+                      dispute, 1, 1,\n\
+                      resolve, 1, 1,    \n";
+        let txs: Vec<_> = collect(actual, InputFormat::Csv)
+            .unwrap()
+            .into_iter()
+            .map(|(_, tx)| (tx._type, tx.client, tx.tx, tx.amount))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (
+                    TransactionType::Deposit,
+                    ClientId(1u16),
+                    TxId(1u32),
+                    Some(dec!(1.2))
+                ),
+                (
+                    TransactionType::Deposit,
+                    ClientId(1u16),
+                    TxId(2u32),
+                    Some(dec!(1.2))
+                ),
+                (
+                    TransactionType::Deposit,
+                    ClientId(1u16),
+                    TxId(3u32),
+                    Some(dec!(1.2))
+                ),
+                (TransactionType::Dispute, ClientId(1u16), TxId(1u32), None),
+                (TransactionType::Resolve, ClientId(1u16), TxId(1u32), None),
+            ],
+            txs
+        );
+    }
+
+    #[test]
+    fn csv_column_order() {
+        let actual = "amount, tx, type, client\n1.5, 2, deposit, 3\n";
+        let (_, tx) = collect(actual, InputFormat::Csv).unwrap().remove(0);
+
+        assert_eq!(TransactionType::Deposit, tx._type);
+        assert_eq!(ClientId(3u16), tx.client);
+        assert_eq!(TxId(2u32), tx.tx);
+        assert_eq!(Some(dec!(1.5)), tx.amount);
+    }
+
+    #[test]
+    fn csv_timestamps() {
+        let actual = "type,client,tx,amount,timestamp\n\
+                      deposit,1,1,1, 1700000000\n\
+                      dispute,1,1,,\n";
+        let timestamps: Vec<_> = collect(actual, InputFormat::Csv)
+            .unwrap()
+            .into_iter()
+            .map(|(_, tx)| tx.timestamp)
+            .collect();
+
+        assert_eq!(vec![Some(Timestamp(1_700_000_000)), None], timestamps);
+        assert_eq!(
+            Err(r#"line 2, column timestamp: invalid value "yesterday""#.to_string()),
+            collect(
+                "type,client,tx,amount,timestamp\ndeposit,1,1,1,yesterday\n",
+                InputFormat::Csv
+            )
+        );
+    }
+
+    #[test]
+    fn csv_parse_error() {
+        for (amount, expected) in [
+            (
+                "    1.2    1",
+                r#"line 3, column amount: invalid value "    1.2    1""#,
+            ),
+            (
+                "    1.2asdf",
+                r#"line 3, column amount: invalid value "    1.2asdf""#,
+            ),
+        ] {
+            let actual = format!("type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,{amount}\n");
+
+            assert_eq!(
+                Err(expected.to_string()),
+                collect(&actual, InputFormat::Csv)
+            );
+        }
+
+        let actual = "type,client,tx,amount\ndeposit,1asdf,1,1\n";
+
+        assert_eq!(
+            Err(r#"line 2, column client: invalid value "1asdf""#.to_string()),
+            collect(actual, InputFormat::Csv)
+        );
+
+        let actual = "type,client,amount\ndeposit,1,1\n";
+
+        assert_eq!(
+            Err("line 1: missing column tx".to_string()),
+            collect(actual, InputFormat::Csv)
+        );
+    }
+
+    #[test]
+    fn parse_error_variant() {
+        let actual = "type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,x,1\n";
+        let err = read_txs(actual.as_bytes(), InputFormat::Csv, |_, _| Ok(())).unwrap_err();
+
+        match err {
+            EngineError::Parse { line, column, .. } => {
+                assert_eq!(3, line);
+                assert_eq!(Some("tx".to_string()), column);
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+
+        let actual = "{\"type\":\"deposit\",\"client\":1,\"tx\":1}\n{\n";
+        let err = read_txs(actual.as_bytes(), InputFormat::Jsonl, |_, _| Ok(())).unwrap_err();
+
+        assert!(matches!(
+            err,
+            EngineError::Parse {
+                line: 2,
+                column: None,
+                ..
+            }
+        ));
+    }
+}
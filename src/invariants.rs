@@ -0,0 +1,171 @@
+//! Property tests that drive random transaction sequences through the engine and check the ledger
+//! invariants after every step.
+
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::{
+    account::Account,
+    outcome::TxOutcome,
+    transaction::{Transaction, TransactionType, TxId},
+    Engine,
+};
+
+/// What the test knows about an applied deposit or withdrawal, updated only from the outcomes the
+/// engine reports.
+#[derive(Clone, Copy, Debug)]
+struct Applied {
+    _type: TransactionType,
+    amount: Decimal,
+    disputed: Decimal,
+    charged_back: Decimal,
+}
+
+/// Mostly valid amounts, with the odd negative or excess precision one.
+fn amount() -> impl Strategy<Value = Decimal> {
+    prop_oneof![
+        8 => (0i64..100_000, 0u32..=4).prop_map(|(units, scale)| Decimal::new(units, scale)),
+        1 => (1i64..100_000).prop_map(|units| Decimal::new(-units, 2)),
+        1 => (1i64..100_000).prop_map(|units| Decimal::new(units, 5)),
+    ]
+}
+
+/// Few clients and tx ids, so references and collisions are common.
+fn transaction() -> impl Strategy<Value = Transaction> {
+    let _type = prop_oneof![
+        4 => Just(TransactionType::Deposit),
+        3 => Just(TransactionType::Withdrawal),
+        2 => Just(TransactionType::Dispute),
+        1 => Just(TransactionType::Resolve),
+        1 => Just(TransactionType::Chargeback),
+    ];
+
+    (_type, 1u16..4, 1u32..40, amount(), any::<bool>()).prop_map(
+        |(_type, client, tx, amount, partial)| {
+            let references = !matches!(
+                _type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            );
+
+            Transaction::new(
+                _type,
+                client,
+                tx,
+                (!references || partial).then_some(amount),
+            )
+        },
+    )
+}
+
+/// Records the effect of an applied transaction, following the same rules as the engine for
+/// amounts that weren't given.
+fn record(history: &mut HashMap<TxId, Applied>, tx: &Transaction) {
+    if let TransactionType::Deposit | TransactionType::Withdrawal = tx._type {
+        history.insert(
+            tx.tx,
+            Applied {
+                _type: tx._type,
+                amount: tx.amount.unwrap_or_default(),
+                disputed: Decimal::ZERO,
+                charged_back: Decimal::ZERO,
+            },
+        );
+
+        return;
+    }
+
+    let applied = history
+        .get_mut(&tx.tx)
+        .expect("only known txs can be applied");
+
+    match tx._type {
+        TransactionType::Dispute => {
+            applied.disputed += tx
+                .amount
+                .unwrap_or(applied.amount - applied.disputed - applied.charged_back);
+        }
+        TransactionType::Resolve => applied.disputed -= tx.amount.unwrap_or(applied.disputed),
+        TransactionType::Chargeback => {
+            let amount = tx.amount.unwrap_or(applied.disputed);
+
+            applied.disputed -= amount;
+            applied.charged_back += amount;
+        }
+        TransactionType::Deposit | TransactionType::Withdrawal => unreachable!(),
+    }
+}
+
+fn check(engine: &Engine, history: &HashMap<TxId, Applied>) -> Result<(), TestCaseError> {
+    let mut totals = Decimal::ZERO;
+    let mut held = Decimal::ZERO;
+
+    for account in engine.accounts() {
+        prop_assert_eq!(
+            account.total,
+            account.available + account.held,
+            "{:?}",
+            account
+        );
+        prop_assert!(!account.held.is_sign_negative(), "{:?}", account);
+
+        totals += account.total;
+        held += account.held;
+    }
+
+    // a charged back deposit leaves the client, while a disputed or charged back withdrawal is
+    // credited back to it
+    let mut expected_totals = Decimal::ZERO;
+    let mut expected_held = Decimal::ZERO;
+
+    for applied in history.values() {
+        match applied._type {
+            TransactionType::Deposit => {
+                expected_totals += applied.amount - applied.charged_back;
+            }
+            _ => {
+                expected_totals += applied.disputed + applied.charged_back - applied.amount;
+            }
+        }
+
+        expected_held += applied.disputed;
+    }
+
+    prop_assert_eq!(expected_totals, totals);
+    prop_assert_eq!(expected_held, held);
+
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn ledger_invariants(txs in prop::collection::vec(transaction(), 1..200)) {
+        let mut engine = Engine::new();
+        let mut history = HashMap::new();
+
+        for tx in txs {
+            let before: Option<Account> = engine.account(tx.client).copied();
+            let Ok(outcome) = engine.process(tx) else {
+                // a reused tx id aborts a run before anything is touched
+                continue;
+            };
+            let after = engine.account(tx.client).copied();
+
+            if before.is_some_and(|account| account.locked) {
+                if let TransactionType::Deposit | TransactionType::Withdrawal = tx._type {
+                    prop_assert!(!outcome.is_applied());
+                    prop_assert_eq!(before, after);
+                }
+            }
+
+            if outcome == TxOutcome::Applied {
+                record(&mut history, &tx);
+            }
+
+            check(&engine, &history)?;
+        }
+    }
+}
@@ -0,0 +1,932 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    mem,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    engine::Change,
+    error::EngineError,
+    input::{self, InputFormat},
+    outcome::{TxOutcome, TxRejection},
+    report::RejectedTx,
+    store::{self, LedgerStore},
+    transaction::{
+        ClientId, DisputedAmounts, HistoryRecord, Timestamp, Transaction, TransactionState,
+        TransactionType, TxId,
+    },
+    Engine,
+};
+
+/// The journal format written by this version.
+pub const JOURNAL_VERSION: u32 = 1;
+/// The number of entries appended between each fsync.
+const BATCH_SIZE: usize = 1024;
+
+/// The first line of every journal.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    version: u32,
+}
+
+/// A processed transaction along with its outcome and how it changed its client's balances.
+/// Replaying an entry has to reproduce the same outcome and deltas, which catches a journal that's
+/// replayed on top of the wrong snapshot.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Entry {
+    line: u64,
+    #[serde(rename = "type")]
+    _type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+    rejected: Option<TxRejection>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+impl Entry {
+    fn new(
+        line: u64,
+        tx: &Transaction,
+        outcome: TxOutcome,
+        before: Account,
+        after: Account,
+    ) -> Self {
+        Self {
+            line,
+            _type: tx._type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            timestamp: tx.timestamp,
+            rejected: match outcome {
+                TxOutcome::Applied => None,
+                TxOutcome::Rejected(reason) => Some(reason),
+            },
+            available: after.available - before.available,
+            held: after.held - before.held,
+            total: after.total - before.total,
+            locked: after.locked,
+        }
+    }
+
+    fn transaction(&self) -> Transaction {
+        Transaction {
+            _type: self._type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+            state: TransactionState::Open,
+            disputes: DisputedAmounts::default(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// An append only write-ahead journal of every transaction a run processes.
+///
+/// Transactions are journaled in batches. Each one is worked out against the engine with the
+/// batch's earlier changes staged on top, and the engine's store only takes the batch's changes
+/// once all of its entries are fsynced. Nothing else a batch produces, its rejected transactions
+/// included, is written anywhere until then either, so after a crash the journal is always at
+/// least as far along as the store and any output. Opening a journal replays it into the engine and
+/// reports the last input line it covers, which is where the interrupted run is resumed from.
+#[derive(Debug)]
+pub struct Journal {
+    writer: BufWriter<File>,
+    staged: Staged,
+    pending: Vec<RejectedTx>,
+    resume_after: u64,
+}
+
+/// The changes of the batch being journaled, in the order they were made, along with the state
+/// they leave behind so later transactions of the batch see it.
+#[derive(Debug, Default)]
+struct Staged {
+    changes: Vec<(u64, Change)>,
+    accounts: HashMap<ClientId, Account>,
+    transactions: BTreeMap<TxId, HistoryRecord>,
+    claims: BTreeMap<TxId, Option<u64>>,
+}
+
+/// A store that reads the staged batch on top of `store` and only writes to the batch.
+struct Overlay<'a, S> {
+    store: &'a S,
+    staged: &'a mut Staged,
+}
+
+impl Journal {
+    /// Opens or creates the journal at `path` and replays every entry in it into `engine`,
+    /// writing the rejected ones to `rejected`. Entries the engine's store already covers, as it
+    /// was checkpointed after them, are only reported. A partially written last entry, left behind
+    /// by a crash, is dropped.
+    pub fn open<S: LedgerStore, W: io::Write>(
+        path: &Path,
+        engine: &mut Engine<S>,
+        mut rejected: Option<&mut csv::Writer<W>>,
+    ) -> Result<Self, EngineError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut reader = BufReader::new(&mut file);
+        let mut record = String::new();
+        let mut complete = 0;
+        let checkpointed = engine.store().checkpointed().unwrap_or(0);
+        let mut resume_after = checkpointed;
+
+        while reader.read_line(&mut record)? > 0 {
+            // only a crash mid append leaves a line without its newline
+            if !record.ends_with('\n') {
+                break;
+            }
+
+            if complete == 0 {
+                let Header { version } = serde_json::from_str(&record).map_err(journal_error)?;
+
+                if version != JOURNAL_VERSION {
+                    return Err(EngineError::Journal {
+                        message: format!(
+                            "unsupported version {version}, expected {JOURNAL_VERSION}"
+                        ),
+                    });
+                }
+            } else {
+                let entry: Entry = serde_json::from_str(&record).map_err(journal_error)?;
+
+                match entry.line > checkpointed {
+                    true => replay(engine, &entry, rejected.as_deref_mut())?,
+                    false => report(engine, &entry, rejected.as_deref_mut())?,
+                }
+
+                resume_after = resume_after.max(entry.line);
+            }
+
+            complete += record.len() as u64;
+            record.clear();
+        }
+
+        file.set_len(complete)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let mut writer = BufWriter::new(file);
+
+        if complete == 0 {
+            serde_json::to_writer(
+                &mut writer,
+                &Header {
+                    version: JOURNAL_VERSION,
+                },
+            )
+            .map_err(io::Error::from)?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(Self {
+            writer,
+            staged: Staged::default(),
+            pending: Vec::new(),
+            resume_after,
+        })
+    }
+
+    /// Returns the last input line the journal already covers. Anything up to and including it
+    /// has been replayed and must not be processed again.
+    pub fn resume_after(&self) -> u64 {
+        self.resume_after
+    }
+
+    /// Appends a transaction read from `line` to the journal and stages what it changes. The
+    /// change reaches `engine`, and its rejection, if any, the report, once its batch has been
+    /// fsynced.
+    pub fn process<S: LedgerStore, W: io::Write>(
+        &mut self,
+        engine: &mut Engine<S>,
+        line: u64,
+        tx: Transaction,
+        rejected: Option<&mut csv::Writer<W>>,
+    ) -> Result<TxOutcome, EngineError> {
+        let mut staged = engine.over(Overlay {
+            store: engine.store(),
+            staged: &mut self.staged,
+        });
+        let before = account(&staged, tx.client)?;
+        let change = staged.prepare(line, &tx)?;
+        let outcome = change.outcome;
+        let after = change.account.unwrap_or(before);
+
+        serde_json::to_writer(
+            &mut self.writer,
+            &Entry::new(line, &tx, outcome, before, after),
+        )
+        .map_err(io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        staged.commit(line, change.clone()).map_err(Into::into)?;
+
+        if let TxOutcome::Rejected(reason) = outcome {
+            if rejected.is_some() {
+                let rejection = staged.rejection(line, &tx, reason).map_err(Into::into)?;
+
+                self.pending.extend(rejection);
+            }
+        }
+
+        self.staged.changes.push((line, change));
+
+        if self.staged.changes.len() == BATCH_SIZE {
+            self.sync(engine, rejected)?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Fsyncs every appended entry, then commits the staged batch to `engine` and writes the
+    /// rejections held back until now.
+    pub fn sync<S: LedgerStore, W: io::Write>(
+        &mut self,
+        engine: &mut Engine<S>,
+        rejected: Option<&mut csv::Writer<W>>,
+    ) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+
+        for (line, change) in mem::take(&mut self.staged).changes {
+            engine.commit(line, change).map_err(Into::into)?;
+        }
+
+        if let Some(rejected) = rejected {
+            for rejection in mem::take(&mut self.pending) {
+                rejected.serialize(rejection)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Empties the journal once its entries are covered by a snapshot or a finished store, keeping
+    /// only the header.
+    pub fn clear(mut self) -> Result<(), EngineError> {
+        let mut header = Vec::new();
+
+        serde_json::to_writer(
+            &mut header,
+            &Header {
+                version: JOURNAL_VERSION,
+            },
+        )
+        .map_err(io::Error::from)?;
+        header.push(b'\n');
+
+        self.writer.flush()?;
+
+        let file = self.writer.get_mut();
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// Feeds every transaction read from `input` after the journal's resume line through `engine`,
+/// journaling each one.
+pub(crate) fn process<S, R, W>(
+    journal: &mut Journal,
+    engine: &mut Engine<S>,
+    input: R,
+    format: InputFormat,
+    mut rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(), EngineError>
+where
+    S: LedgerStore,
+    R: io::Read,
+    W: io::Write,
+{
+    let resume_after = journal.resume_after();
+
+    let read = input::read_txs(input, format, |line, tx| {
+        if line > resume_after {
+            journal.process(engine, line, tx, rejected.as_deref_mut())?;
+        }
+
+        Ok(())
+    });
+
+    // whatever was processed before an error is kept, as it would be without a journal
+    journal.sync(engine, rejected)?;
+
+    read
+}
+
+impl<S: LedgerStore> LedgerStore for Overlay<'_, S> {
+    type Error = S::Error;
+
+    fn account(&self, client: ClientId) -> Result<Option<Account>, Self::Error> {
+        match self.staged.accounts.get(&client) {
+            Some(account) => Ok(Some(*account)),
+            None => self.store.account(client),
+        }
+    }
+
+    fn account_mut(&mut self, client: ClientId) -> Result<Option<&mut Account>, Self::Error> {
+        if !self.staged.accounts.contains_key(&client) {
+            let Some(account) = self.store.account(client)? else {
+                return Ok(None);
+            };
+
+            self.staged.accounts.insert(client, account);
+        }
+
+        Ok(self.staged.accounts.get_mut(&client))
+    }
+
+    fn insert_account(&mut self, account: Account) -> Result<(), Self::Error> {
+        self.staged.accounts.insert(account.client, account);
+
+        Ok(())
+    }
+
+    fn tx(&self, tx: TxId) -> Result<Option<HistoryRecord>, Self::Error> {
+        match self.staged.transactions.get(&tx) {
+            Some(record) => Ok(Some(record.clone())),
+            None => self.store.tx(tx),
+        }
+    }
+
+    fn tx_mut(&mut self, tx: TxId) -> Result<Option<&mut HistoryRecord>, Self::Error> {
+        if !self.staged.transactions.contains_key(&tx) {
+            let Some(record) = self.store.tx(tx)? else {
+                return Ok(None);
+            };
+
+            self.staged.transactions.insert(tx, record);
+        }
+
+        Ok(self.staged.transactions.get_mut(&tx))
+    }
+
+    fn insert_tx(&mut self, tx: TxId, record: HistoryRecord) -> Result<(), Self::Error> {
+        self.staged.transactions.insert(tx, record);
+
+        Ok(())
+    }
+
+    fn claimed(&self, tx: TxId) -> Result<bool, Self::Error> {
+        match self.staged.claims.contains_key(&tx) {
+            true => Ok(true),
+            false => self.store.claimed(tx),
+        }
+    }
+
+    fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, Self::Error> {
+        match self.staged.claims.get(&tx) {
+            Some(line) => Ok(*line),
+            None => self.store.claimed_on(tx),
+        }
+    }
+
+    fn insert_claim(&mut self, tx: TxId, line: Option<u64>) -> Result<(), Self::Error> {
+        self.staged.claims.insert(tx, line);
+
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, Self::Error> {
+        let mut accounts = self.store.accounts()?;
+
+        accounts.retain(|account| !self.staged.accounts.contains_key(&account.client));
+        accounts.extend(self.staged.accounts.values().copied());
+
+        Ok(accounts)
+    }
+
+    fn transactions(
+        &self,
+    ) -> impl Iterator<Item = Result<(TxId, HistoryRecord), Self::Error>> + '_ {
+        let staged = self.staged.transactions.iter();
+
+        store::merged(
+            self.store.transactions(),
+            staged.map(|(tx, record)| (*tx, record.clone())),
+        )
+    }
+
+    fn claims(&self) -> impl Iterator<Item = Result<(TxId, Option<u64>), Self::Error>> + '_ {
+        let staged = self.staged.claims.iter();
+
+        store::merged(self.store.claims(), staged.map(|(tx, line)| (*tx, *line)))
+    }
+}
+
+fn replay<S: LedgerStore, W: io::Write>(
+    engine: &mut Engine<S>,
+    entry: &Entry,
+    rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(), EngineError> {
+    let tx = entry.transaction();
+    let before = account(engine, tx.client)?;
+    let outcome = engine.process_line(entry.line, tx)?;
+    let replayed = Entry::new(
+        entry.line,
+        &tx,
+        outcome,
+        before,
+        account(engine, tx.client)?,
+    );
+
+    if replayed != *entry {
+        return Err(EngineError::Journal {
+            message: format!(
+                "line {} replays differently than it was journaled, was it started from another \
+                 snapshot?",
+                entry.line
+            ),
+        });
+    }
+
+    report(engine, entry, rejected)
+}
+
+/// Writes the rejection of a journaled transaction to `rejected`, if it was rejected at all.
+fn report<S: LedgerStore, W: io::Write>(
+    engine: &Engine<S>,
+    entry: &Entry,
+    rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(), EngineError> {
+    if let (Some(reason), Some(rejected)) = (entry.rejected, rejected) {
+        if let Some(rejection) = engine
+            .rejection(entry.line, &entry.transaction(), reason)
+            .map_err(Into::into)?
+        {
+            rejected.serialize(rejection)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the account of `client`, or an empty one when it hasn't been created yet.
+fn account<S: LedgerStore>(engine: &Engine<S>, client: ClientId) -> Result<Account, EngineError> {
+    let account = engine.store().account(client).map_err(Into::into)?;
+
+    Ok(account.unwrap_or_else(|| Account::new(client)))
+}
+
+fn journal_error(err: serde_json::Error) -> EngineError {
+    EngineError::Journal {
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+    use crate::{
+        account::SortBy,
+        engine::DuplicatePolicy,
+        store::{DiskStore, MemoryStore},
+        transaction::HistoryRecord,
+    };
+
+    const INPUT: &str = "type,client,tx,amount,timestamp
+deposit,1,1,10,100
+deposit,2,2,5,
+withdrawal,1,3,20,300
+dispute,1,1,,400
+deposit,3,4,-1,500
+withdrawal,2,5,1.5,600
+resolve,1,1,,
+chargeback,2,2,,800
+";
+
+    fn path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("payments-engine-{}-{name}.jsonl", process::id()))
+    }
+
+    fn snapshot<S: LedgerStore>(engine: &Engine<S>) -> String {
+        let mut bytes = Vec::new();
+
+        engine.write_snapshot(&mut bytes).unwrap();
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// Runs `input` through a fresh engine without a journal.
+    fn uninterrupted(input: &str) -> (String, String) {
+        let mut engine = Engine::new();
+        let mut rejected = csv::Writer::from_writer(vec![]);
+
+        crate::process(
+            &mut engine,
+            input.as_bytes(),
+            InputFormat::Csv,
+            0,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        (
+            snapshot(&engine),
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn resume_after_crash() {
+        let path = path("resume");
+        let lines: Vec<_> = INPUT.lines().collect();
+
+        {
+            let mut engine = Engine::new();
+            let mut journal =
+                Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+            let partial = lines[..5].join("\n");
+
+            process(
+                &mut journal,
+                &mut engine,
+                partial.as_bytes(),
+                InputFormat::Csv,
+                None::<&mut csv::Writer<Vec<u8>>>,
+            )
+            .unwrap();
+        }
+
+        // a crash in the middle of the next append
+        let mut torn = fs::read(&path).unwrap();
+
+        torn.extend_from_slice(br#"{"line":6,"type":"dep"#);
+        fs::write(&path, torn).unwrap();
+
+        let mut engine = Engine::new();
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let mut journal = Journal::open(&path, &mut engine, Some(&mut rejected)).unwrap();
+
+        assert_eq!(5, journal.resume_after());
+        assert_eq!(uninterrupted(&lines[..5].join("\n")).0, snapshot(&engine));
+
+        process(
+            &mut journal,
+            &mut engine,
+            INPUT.as_bytes(),
+            InputFormat::Csv,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let (expected, expected_rejected) = uninterrupted(INPUT);
+
+        assert_eq!(expected, snapshot(&engine));
+        assert_eq!(
+            expected_rejected,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap()
+        );
+
+        // the torn entry was dropped rather than left in front of the new ones
+        let journaled = fs::read_to_string(&path).unwrap();
+
+        assert_eq!(lines.len(), journaled.lines().count());
+        assert!(journaled.ends_with('\n'));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    /// A store that stops taking writes once `writes` have been made, like a run killed after a
+    /// batch was journaled but before the engine applied all of it.
+    struct KilledStore {
+        store: MemoryStore,
+        writes: usize,
+    }
+
+    impl KilledStore {
+        fn write(&mut self) -> Result<&mut MemoryStore, EngineError> {
+            match self.writes.checked_sub(1) {
+                Some(writes) => self.writes = writes,
+                None => return Err(io::Error::other("killed").into()),
+            }
+
+            Ok(&mut self.store)
+        }
+    }
+
+    impl LedgerStore for KilledStore {
+        type Error = EngineError;
+
+        fn account(&self, client: ClientId) -> Result<Option<Account>, Self::Error> {
+            let Ok(account) = self.store.account(client);
+
+            Ok(account)
+        }
+
+        fn account_mut(&mut self, client: ClientId) -> Result<Option<&mut Account>, Self::Error> {
+            let Ok(account) = self.write()?.account_mut(client);
+
+            Ok(account)
+        }
+
+        fn insert_account(&mut self, account: Account) -> Result<(), Self::Error> {
+            let Ok(()) = self.write()?.insert_account(account);
+
+            Ok(())
+        }
+
+        fn tx(&self, tx: TxId) -> Result<Option<HistoryRecord>, Self::Error> {
+            let Ok(record) = self.store.tx(tx);
+
+            Ok(record)
+        }
+
+        fn tx_mut(&mut self, tx: TxId) -> Result<Option<&mut HistoryRecord>, Self::Error> {
+            let Ok(record) = self.write()?.tx_mut(tx);
+
+            Ok(record)
+        }
+
+        fn insert_tx(&mut self, tx: TxId, record: HistoryRecord) -> Result<(), Self::Error> {
+            let Ok(()) = self.write()?.insert_tx(tx, record);
+
+            Ok(())
+        }
+
+        fn accounts(&self) -> Result<Vec<Account>, Self::Error> {
+            let Ok(accounts) = self.store.accounts();
+
+            Ok(accounts)
+        }
+
+        fn transactions(
+            &self,
+        ) -> impl Iterator<Item = Result<(TxId, HistoryRecord), Self::Error>> + '_ {
+            self.store
+                .transactions()
+                .map(|stored| stored.map_err(Into::into))
+        }
+
+        fn claimed(&self, tx: TxId) -> Result<bool, Self::Error> {
+            let Ok(claimed) = self.store.claimed(tx);
+
+            Ok(claimed)
+        }
+
+        fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, Self::Error> {
+            let Ok(line) = self.store.claimed_on(tx);
+
+            Ok(line)
+        }
+
+        fn insert_claim(&mut self, tx: TxId, line: Option<u64>) -> Result<(), Self::Error> {
+            let Ok(()) = self.write()?.insert_claim(tx, line);
+
+            Ok(())
+        }
+
+        fn claims(&self) -> impl Iterator<Item = Result<(TxId, Option<u64>), Self::Error>> + '_ {
+            self.store.claims().map(|claim| claim.map_err(Into::into))
+        }
+    }
+
+    #[test]
+    fn resume_after_killed_apply() {
+        let path = path("killed");
+
+        {
+            // lines 2 to 4 make ten writes, so the run is killed applying the dispute on line 5
+            // of the only batch
+            let store = KilledStore {
+                store: MemoryStore::default(),
+                writes: 10,
+            };
+            let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+            let mut journal =
+                Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+            let err = process(
+                &mut journal,
+                &mut engine,
+                INPUT.as_bytes(),
+                InputFormat::Csv,
+                None::<&mut csv::Writer<Vec<u8>>>,
+            )
+            .unwrap_err();
+
+            assert!(matches!(err, EngineError::Io(_)), "{err:?}");
+        }
+
+        // the whole batch was fsynced before any of it was applied, so it's replayed rather than
+        // lost
+        let mut engine = Engine::new();
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let mut journal = Journal::open(&path, &mut engine, Some(&mut rejected)).unwrap();
+
+        assert_eq!(9, journal.resume_after());
+
+        process(
+            &mut journal,
+            &mut engine,
+            INPUT.as_bytes(),
+            InputFormat::Csv,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let (expected, expected_rejected) = uninterrupted(INPUT);
+
+        assert_eq!(expected, snapshot(&engine));
+        assert_eq!(
+            expected_rejected,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap()
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resume_after_lost_batch() {
+        let path = path("lost");
+        let header = "{\"version\":1}\n";
+        // a store that outlives the crash, as one on disk would
+        let mut engine = Engine::new();
+
+        {
+            let mut journal =
+                Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+
+            input::read_txs(INPUT.as_bytes(), InputFormat::Csv, |line, tx| {
+                journal.process(&mut engine, line, tx, None::<&mut csv::Writer<Vec<u8>>>)?;
+
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        // the batch was never fsynced, so it never reached the store
+        assert_eq!(snapshot(&Engine::new()), snapshot(&engine));
+
+        // a crash loses every entry that wasn't fsynced
+        fs::write(&path, header).unwrap();
+
+        let mut rejected = csv::Writer::from_writer(vec![]);
+
+        process(
+            &mut Journal::open(&path, &mut engine, Some(&mut rejected)).unwrap(),
+            &mut engine,
+            INPUT.as_bytes(),
+            InputFormat::Csv,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let (expected, expected_rejected) = uninterrupted(INPUT);
+
+        assert_eq!(expected, snapshot(&engine));
+        assert_eq!(
+            expected_rejected,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap()
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resume_from_checkpointed_store() {
+        let path = path("checkpointed");
+        let dir = env::temp_dir().join(format!("payments-engine-{}-checkpointed", process::id()));
+
+        {
+            let mut store = DiskStore::open(&dir).unwrap();
+
+            store.capacity = 4;
+
+            let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+
+            process(
+                &mut Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap(),
+                &mut engine,
+                INPUT.as_bytes(),
+                InputFormat::Csv,
+                None::<&mut csv::Writer<Vec<u8>>>,
+            )
+            .unwrap();
+        }
+
+        // the run is killed before it finishes, so the store is only as far along as its last
+        // checkpoint, past the rejections on lines 4 and 6, while the journal covers every line
+        let store = DiskStore::open(&dir).unwrap();
+        let checkpointed = store.checkpointed().unwrap();
+
+        assert!((6..9).contains(&checkpointed), "{checkpointed}");
+
+        let mut engine = Engine::with_store(store, DuplicatePolicy::Abort);
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let mut journal = Journal::open(&path, &mut engine, Some(&mut rejected)).unwrap();
+
+        // only the entries after the checkpoint are replayed, the rest are only reported
+        assert_eq!(9, journal.resume_after());
+
+        process(
+            &mut journal,
+            &mut engine,
+            INPUT.as_bytes(),
+            InputFormat::Csv,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let (expected, expected_rejected) = uninterrupted(INPUT);
+
+        assert_eq!(expected, snapshot(&engine));
+        assert_eq!(
+            expected_rejected,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap()
+        );
+
+        drop(engine);
+        fs::remove_file(path).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replay_mismatch() {
+        let path = path("mismatch");
+
+        {
+            let mut engine = Engine::new();
+            let mut journal =
+                Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+
+            process(
+                &mut journal,
+                &mut engine,
+                INPUT.as_bytes(),
+                InputFormat::Csv,
+                None::<&mut csv::Writer<Vec<u8>>>,
+            )
+            .unwrap();
+        }
+
+        // an engine with an earlier deposit, as if restored from another snapshot, lets the
+        // journaled withdrawal through
+        let mut engine = Engine::new();
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                9,
+                Some(Decimal::TEN),
+            ))
+            .unwrap();
+
+        let err = Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap_err();
+
+        match err {
+            EngineError::Journal { message } => assert!(message.contains("line 4"), "{message}"),
+            other => panic!("expected a journal error, got {other:?}"),
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn clear() {
+        let path = path("clear");
+        let mut engine = Engine::new();
+        let mut journal =
+            Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+
+        process(
+            &mut journal,
+            &mut engine,
+            INPUT.as_bytes(),
+            InputFormat::Csv,
+            None::<&mut csv::Writer<Vec<u8>>>,
+        )
+        .unwrap();
+        journal.clear().unwrap();
+
+        let mut engine = Engine::new();
+        let journal = Journal::open(&path, &mut engine, None::<&mut csv::Writer<Vec<u8>>>).unwrap();
+
+        assert_eq!(0, journal.resume_after());
+        assert_eq!(0, engine.sorted_accounts(SortBy::Client).len());
+        assert_eq!("{\"version\":1}\n", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+}
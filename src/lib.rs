@@ -0,0 +1,1302 @@
+pub mod account;
+pub mod config;
+pub mod de;
+mod engine;
+pub mod error;
+pub mod generate;
+mod history;
+pub mod input;
+#[cfg(test)]
+mod invariants;
+pub mod journal;
+pub mod outcome;
+pub mod output;
+mod owners;
+pub mod report;
+mod seen;
+mod sharded;
+pub mod snapshot;
+pub mod store;
+pub mod transaction;
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    num::NonZeroUsize,
+    path::Path,
+};
+
+use account::Account;
+pub use config::Config;
+pub use engine::{DuplicatePolicy, Engine};
+pub use error::EngineError;
+use input::InputFormat;
+pub use journal::Journal;
+use outcome::TxOutcome;
+pub use store::{DiskStore, LedgerStore, MemoryStore};
+
+/// What a run did besides writing the accounts.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// The last input line an existing journal already covered, when the run resumed from one.
+    pub resumed_after: Option<u64>,
+}
+
+/// Processes the transactions read from `input` and writes the resulting accounts to `output`.
+pub fn run<R, W>(input: R, output: W, config: &Config) -> Result<Summary, EngineError>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    // conflicting options are reported before the rejected report is truncated
+    config.check()?;
+
+    let mut rejected = config
+        .rejected
+        .as_ref()
+        .map(csv::Writer::from_path)
+        .transpose()?;
+    let (mut accounts, summary) = match (&config.store, config.threads) {
+        (Some(path), _) => run_single(DiskStore::open(path)?, input, config, rejected.as_mut())?,
+        (None, Some(threads)) => run_sharded(threads, input, config, rejected.as_mut())?,
+        (None, None) => run_single(MemoryStore::default(), input, config, rejected.as_mut())?,
+    };
+
+    accounts.sort_unstable_by(|a, b| config.sort.compare(a, b));
+    output::write_accounts(output, config.output_format, &accounts)?;
+
+    Ok(summary)
+}
+
+/// Processes every transaction on the calling thread, keeping the ledger in `store` and
+/// journaling each transaction when `config` asks for it. A store left behind by an interrupted
+/// run already holds the snapshot it was loaded with, and that run is resumed.
+fn run_single<S, R, W>(
+    store: S,
+    input: R,
+    config: &Config,
+    mut rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(Vec<Account>, Summary), EngineError>
+where
+    S: LedgerStore,
+    R: io::Read,
+    W: io::Write,
+{
+    let checkpointed = store.checkpointed();
+    let mut engine = match (&config.load_snapshot, checkpointed) {
+        (Some(path), None) => {
+            if holds_ledger(&store)? {
+                return Err(EngineError::Snapshot {
+                    message: "the store already holds a ledger to continue from".to_string(),
+                });
+            }
+
+            Engine::restore(
+                store,
+                BufReader::new(File::open(path)?),
+                config.on_duplicate,
+            )?
+        }
+        _ => Engine::with_store(store, config.on_duplicate),
+    }
+    .with_dispute_window(config.dispute_window);
+    let mut journal = config
+        .journal
+        .as_ref()
+        .map(|path| Journal::open(path, &mut engine, rejected.as_deref_mut()))
+        .transpose()?;
+    let summary = Summary {
+        resumed_after: journal
+            .as_ref()
+            .map(Journal::resume_after)
+            .or(checkpointed)
+            .filter(|line| *line > 0),
+    };
+
+    match journal.as_mut() {
+        Some(journal) => journal::process(
+            journal,
+            &mut engine,
+            input,
+            config.input_format,
+            rejected.as_deref_mut(),
+        )?,
+        None => process(
+            &mut engine,
+            input,
+            config.input_format,
+            checkpointed.unwrap_or(0),
+            rejected.as_deref_mut(),
+        )?,
+    }
+
+    if let Some(rejected) = rejected {
+        rejected.flush()?;
+    }
+
+    if let Some(path) = &config.save_snapshot {
+        save_snapshot(&engine, path)?;
+    }
+
+    // the snapshot or the store covers everything the journal does. The journal is cleared before
+    // the store finishes, so a crash in between resumes from the store's last checkpoint rather
+    // than replaying the journal on top of a finished run
+    if let Some(journal) = journal {
+        if config.save_snapshot.is_some() || config.store.is_some() {
+            journal.clear()?;
+        }
+    }
+
+    engine.finish()?;
+
+    let accounts = engine.store().accounts().map_err(Into::into)?;
+
+    Ok((accounts, summary))
+}
+
+/// Processes the transactions with clients sharded across `threads` worker threads, keeping the
+/// ledger in memory.
+fn run_sharded<R, W>(
+    threads: NonZeroUsize,
+    input: R,
+    config: &Config,
+    mut rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(Vec<Account>, Summary), EngineError>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let engine = match &config.load_snapshot {
+        Some(path) => {
+            Engine::from_snapshot(BufReader::new(File::open(path)?), config.on_duplicate)?
+        }
+        None => Engine::with_duplicates(config.on_duplicate),
+    }
+    .with_dispute_window(config.dispute_window);
+    let engine = sharded::process(
+        engine,
+        input,
+        config.input_format,
+        threads,
+        rejected.as_deref_mut(),
+    )?;
+
+    if let Some(rejected) = rejected {
+        rejected.flush()?;
+    }
+
+    if let Some(path) = &config.save_snapshot {
+        save_snapshot(&engine, path)?;
+    }
+
+    Ok((engine.accounts().copied().collect(), Summary::default()))
+}
+
+/// Writes a snapshot of `engine` next to `path` and moves it into place once it's complete, so an
+/// interrupted run never leaves a partial snapshot behind.
+fn save_snapshot<S: LedgerStore>(engine: &Engine<S>, path: &Path) -> Result<(), EngineError> {
+    let mut partial = path.as_os_str().to_owned();
+
+    partial.push(".partial");
+
+    let file = File::create(&partial)?;
+
+    engine.write_snapshot(BufWriter::new(&file))?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+
+    Ok(())
+}
+
+/// Returns whether `store` holds any account or claimed tx id.
+fn holds_ledger<S: LedgerStore>(store: &S) -> Result<bool, EngineError> {
+    if !store.accounts().map_err(Into::into)?.is_empty() {
+        return Ok(true);
+    }
+
+    let claim = store.claims().next().transpose().map_err(Into::into)?;
+
+    Ok(claim.is_some())
+}
+
+/// Feeds every transaction read from `input` after `resume_after` through `engine`. Rejected
+/// transactions are written to `rejected` along with the line they were read from.
+fn process<S, R, W>(
+    engine: &mut Engine<S>,
+    input: R,
+    format: InputFormat,
+    resume_after: u64,
+    mut rejected: Option<&mut csv::Writer<W>>,
+) -> Result<(), EngineError>
+where
+    S: LedgerStore,
+    R: io::Read,
+    W: io::Write,
+{
+    input::read_txs(input, format, |line, tx| {
+        if line <= resume_after {
+            return Ok(());
+        }
+
+        if let TxOutcome::Rejected(reason) = engine.process_line(line, tx)? {
+            if let Some(rejected) = rejected.as_mut() {
+                if let Some(rejection) = engine.rejection(line, &tx, reason).map_err(Into::into)? {
+                    rejected.serialize(rejection)?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        account::{Account, MAX_BALANCE},
+        outcome::{TxOutcome, TxRejection},
+        transaction::{
+            ClientId, DisputedAmounts, Transaction, TransactionState, TransactionType, TxId,
+        },
+    };
+
+    #[test]
+    fn e2e() {
+        let expected = include_str!("../test_data/end_to_end_answer.csv");
+        let mut output = vec![];
+
+        run(
+            include_str!("../test_data/end_to_end.csv").as_bytes(),
+            &mut output,
+            &Config::default(),
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn e2e_threads() {
+        let expected = include_str!("../test_data/end_to_end_answer.csv");
+        let mut output = vec![];
+
+        run(
+            include_str!("../test_data/end_to_end.csv").as_bytes(),
+            &mut output,
+            &Config {
+                threads: std::num::NonZeroUsize::new(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn e2e_store() {
+        let expected = include_str!("../test_data/end_to_end_answer.csv");
+        let dir =
+            std::env::temp_dir().join(format!("payments-engine-{}-store", std::process::id()));
+        let config = Config {
+            store: Some(dir.clone()),
+            ..Default::default()
+        };
+        let mut output = vec![];
+
+        run(
+            include_str!("../test_data/end_to_end.csv").as_bytes(),
+            &mut output,
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+
+        let err = run(
+            include_str!("../test_data/end_to_end.csv").as_bytes(),
+            io::sink(),
+            &Config {
+                threads: std::num::NonZeroUsize::new(2),
+                ..config
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, EngineError::Usage { .. }));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn conflicting_options_keep_the_report() {
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-{}-kept-rejected.csv",
+            std::process::id()
+        ));
+
+        fs::write(&path, "kept").unwrap();
+
+        let err = run(
+            include_str!("../test_data/end_to_end.csv").as_bytes(),
+            io::sink(),
+            &Config {
+                rejected: Some(path.clone()),
+                threads: std::num::NonZeroUsize::new(2),
+                journal: Some(path.with_extension("jsonl")),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, EngineError::Usage { .. }));
+        assert_eq!("kept", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejected_report() {
+        let actual = r#"type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, -1.0
+withdrawal, 1, 3, 2.0
+dispute, 2, 1,
+resolve, 1, 1,
+deposit, 1, 1, 5.0
+withdrawal, 3, 3, 1.0
+"#;
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let mut engine = Engine::with_duplicates(DuplicatePolicy::Report);
+
+        process(
+            &mut engine,
+            actual.as_bytes(),
+            InputFormat::Csv,
+            0,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(rejected.into_inner().unwrap()).unwrap();
+        let expected = r#"line,type,client,tx,amount,reason,original_line
+3,deposit,1,2,-1.0,invalid_data,
+4,withdrawal,1,3,2.0,insufficient_funds,
+5,dispute,2,1,,client_mismatch,
+6,resolve,1,1,,not_disputed,
+7,deposit,1,1,5.0,duplicate_tx,2
+8,withdrawal,3,3,1.0,duplicate_tx,4
+"#;
+
+        assert_eq!(expected, actual);
+        assert_eq!(dec!(1), engine.account(ClientId(1u16)).unwrap().total);
+        assert_eq!(None, engine.account(ClientId(3u16)));
+    }
+
+    #[test]
+    fn skipped_duplicates() {
+        let actual = r#"type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 1, 5.0
+withdrawal, 1, 2, 2.0
+"#;
+        let mut output = vec![];
+        let config = Config {
+            on_duplicate: DuplicatePolicy::Skip,
+            ..Default::default()
+        };
+
+        run(actual.as_bytes(), &mut output, &config).unwrap();
+
+        let expected = "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n";
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+        assert!(matches!(
+            run(actual.as_bytes(), vec![], &Config::default()),
+            Err(EngineError::DuplicateTx {
+                first: 2,
+                second: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn daily_snapshots() {
+        let day_1 = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,3,3,1\n";
+        let day_2 = "type,client,tx,amount\ndispute,1,1,\nwithdrawal,2,4,2\nchargeback,1,1,\n";
+        let expected = "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,true\n\
+                        2,3.0000,0.0000,3.0000,false\n3,0.0000,0.0000,0.0000,false\n";
+        let dir = std::env::temp_dir();
+
+        for threads in [None, std::num::NonZeroUsize::new(3)] {
+            let snapshot = dir.join(format!(
+                "payments-engine-{}-{threads:?}.json",
+                std::process::id()
+            ));
+            let mut output = vec![];
+
+            run(
+                day_1.as_bytes(),
+                io::sink(),
+                &Config {
+                    threads,
+                    save_snapshot: Some(snapshot.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            run(
+                day_2.as_bytes(),
+                &mut output,
+                &Config {
+                    threads,
+                    load_snapshot: Some(snapshot.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+
+            // the rejected withdrawal's id was claimed on day 1
+            let err = run(
+                "type,client,tx,amount\ndeposit,3,3,1\n".as_bytes(),
+                io::sink(),
+                &Config {
+                    threads,
+                    load_snapshot: Some(snapshot.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+
+            assert!(matches!(
+                err,
+                EngineError::DuplicateRestoredTx {
+                    tx: TxId(3u32),
+                    second: 2,
+                }
+            ));
+
+            fs::remove_file(snapshot).unwrap();
+        }
+    }
+
+    #[test]
+    fn daily_store() {
+        let day_1 = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,3,3,1\n";
+        let day_2 = "type,client,tx,amount\ndispute,1,1,\nwithdrawal,2,4,2\nchargeback,1,1,\n";
+        let expected = "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,true\n\
+                        2,3.0000,0.0000,3.0000,false\n3,0.0000,0.0000,0.0000,false\n";
+        let temp = |name: &str| {
+            std::env::temp_dir().join(format!("payments-engine-{}-{name}", std::process::id()))
+        };
+        let snapshot = temp("daily-store.jsonl");
+
+        run(
+            day_1.as_bytes(),
+            io::sink(),
+            &Config {
+                save_snapshot: Some(snapshot.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for load_snapshot in [None, Some(snapshot.clone())] {
+            let store = temp(&format!("daily-store-{}", load_snapshot.is_some()));
+            let mut output = vec![];
+
+            // day 1 either went through the store or is loaded into it from its snapshot
+            if load_snapshot.is_none() {
+                let config = Config {
+                    store: Some(store.clone()),
+                    ..Default::default()
+                };
+
+                run(day_1.as_bytes(), io::sink(), &config).unwrap();
+            }
+
+            let config = Config {
+                store: Some(store.clone()),
+                load_snapshot,
+                ..Default::default()
+            };
+
+            run(day_2.as_bytes(), &mut output, &config).unwrap();
+
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+
+            let config = Config {
+                store: Some(store.clone()),
+                ..Default::default()
+            };
+            // the rejected withdrawal's id was claimed on a line of day 1's input
+            let err = run(
+                "type,client,tx,amount\ndeposit,3,3,1\n".as_bytes(),
+                io::sink(),
+                &config,
+            )
+            .unwrap_err();
+
+            assert!(matches!(
+                err,
+                EngineError::DuplicateRestoredTx {
+                    tx: TxId(3u32),
+                    second: 2,
+                }
+            ));
+
+            // a snapshot is never loaded on top of the ledger the store already holds
+            let config = Config {
+                store: Some(store.clone()),
+                load_snapshot: Some(snapshot.clone()),
+                ..Default::default()
+            };
+            let err = run(day_2.as_bytes(), io::sink(), &config).unwrap_err();
+
+            assert!(matches!(err, EngineError::Snapshot { .. }), "{err:?}");
+
+            fs::remove_dir_all(store).unwrap();
+        }
+
+        fs::remove_file(snapshot).unwrap();
+    }
+
+    #[test]
+    fn timestamped_disputes() {
+        let day_1 = "type,client,tx,amount,timestamp\ndeposit,1,1,10,1000\ndeposit,2,2,5,1000\n\
+                     deposit,3,3,1,\n";
+        let day_2 = "type,client,tx,amount,timestamp\ndispute,1,1,,87400\ndispute,2,2,,87401\n\
+                     dispute,3,3,,90000\n";
+        let expected = "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n\
+                        2,5.0000,0.0000,5.0000,false\n3,0.0000,1.0000,1.0000,false\n";
+        let dir = std::env::temp_dir();
+
+        for threads in [None, std::num::NonZeroUsize::new(3)] {
+            let snapshot = dir.join(format!(
+                "payments-engine-{}-timestamped-{threads:?}.json",
+                std::process::id()
+            ));
+            let rejected = snapshot.with_extension("csv");
+            let mut output = vec![];
+
+            run(
+                day_1.as_bytes(),
+                io::sink(),
+                &Config {
+                    threads,
+                    save_snapshot: Some(snapshot.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            run(
+                day_2.as_bytes(),
+                &mut output,
+                &Config {
+                    threads,
+                    load_snapshot: Some(snapshot.clone()),
+                    rejected: Some(rejected.clone()),
+                    dispute_window: Some(86_400),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+            assert_eq!(
+                "line,type,client,tx,amount,reason,original_line\n\
+                 3,dispute,2,2,,dispute_window_expired,\n",
+                fs::read_to_string(&rejected).unwrap()
+            );
+
+            fs::remove_file(snapshot).unwrap();
+            fs::remove_file(rejected).unwrap();
+        }
+    }
+
+    #[test]
+    fn resume_from_journal() {
+        let journal = std::env::temp_dir().join(format!(
+            "payments-engine-{}-journal.jsonl",
+            std::process::id()
+        ));
+        let config = Config {
+            journal: Some(journal.clone()),
+            ..Default::default()
+        };
+        let input = include_str!("../test_data/end_to_end.csv");
+        let expected = include_str!("../test_data/end_to_end_answer.csv");
+        let lines = input.lines().count() as u64;
+
+        for resumed_after in [None, Some(lines)] {
+            let mut output = vec![];
+            let summary = run(input.as_bytes(), &mut output, &config).unwrap();
+
+            assert_eq!(Summary { resumed_after }, summary);
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+        }
+
+        let err = run(
+            input.as_bytes(),
+            io::sink(),
+            &Config {
+                threads: std::num::NonZeroUsize::new(2),
+                ..config
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, EngineError::Usage { .. }));
+
+        fs::remove_file(journal).unwrap();
+    }
+
+    #[test]
+    fn e2e_jsonl() {
+        let expected = include_str!("../test_data/end_to_end_answer.csv");
+        let mut output = vec![];
+
+        run(
+            include_str!("../test_data/end_to_end.jsonl").as_bytes(),
+            &mut output,
+            &Config {
+                input_format: InputFormat::Jsonl,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn simple_des() {
+        let actual = r#"type, client, tx, amount
+deposit,1,1,1.0
+deposit, 2, 2, 2.0
+deposit,     1, 3,                    2.0
+withdrawal, 1, 4,    1.5
+withdrawal, 2, 5, 3.0
+chargeback, 1, 1,
+dispute, 2, 2,
+resolve, 2, 2,
+"#;
+        let mut actual = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(actual.as_bytes());
+        let actual = actual.deserialize();
+        let mut accum = Vec::default();
+
+        for actual in actual {
+            match actual {
+                Ok::<Transaction, _>(actual) => accum.push(actual),
+                Err(err) => assert_eq!("", format!("{:?}", err)),
+            }
+        }
+
+        assert_eq!(
+            vec![
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::ONE)),
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::TWO)),
+                Transaction::new(TransactionType::Deposit, 1, 3, Some(Decimal::TWO)),
+                Transaction::new(TransactionType::Withdrawal, 1, 4, Some(dec!(1.5))),
+                Transaction::new(TransactionType::Withdrawal, 2, 5, Some(dec!(3.0))),
+                Transaction::new(TransactionType::Chargeback, 1, 1, None),
+                Transaction::new(TransactionType::Dispute, 2, 2, None),
+                Transaction::new(TransactionType::Resolve, 2, 2, None),
+            ],
+            accum,
+        )
+    }
+
+    #[test]
+    fn simple_ser() {
+        let mut wtr = csv::WriterBuilder::new().from_writer(vec![]);
+
+        wtr.serialize(Account {
+            client: ClientId(1u16),
+            available: dec!(1.5),
+            held: Decimal::ZERO,
+            total: dec!(1.5),
+            locked: false,
+        })
+        .unwrap();
+        wtr.serialize(Account {
+            client: ClientId(2u16),
+            available: Decimal::TWO,
+            held: Decimal::ZERO,
+            total: Decimal::TWO,
+            locked: true,
+        })
+        .unwrap();
+
+        let actual = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        let expected = r#"client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2,0,2,true
+"#;
+
+        assert_eq!(expected, actual)
+    }
+
+    #[test]
+    fn deposit_and_withdraw_flow() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::ONE));
+        let tx2 = Transaction::new(TransactionType::Deposit, 1, 2, Some(dec!(3)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(dec!(4), account.total);
+        assert_eq!(dec!(4), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        let tx1 = Transaction::new(TransactionType::Withdrawal, 1, 1, Some(Decimal::ONE));
+
+        account.apply_tx(&tx1, None);
+
+        assert_eq!(dec!(3), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(5)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::ONE));
+
+        account.locked = true;
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(dec!(3), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
+    #[test]
+    fn omit_excess_withdrawals() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::ONE));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(3)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(Decimal::ONE, account.total);
+        assert_eq!(Decimal::ONE, account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
+    #[test]
+    fn can_withdraw_to_zero() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(10)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(Decimal::ZERO, account.total);
+        assert_eq!(Decimal::ZERO, account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
+    #[test]
+    fn overflow_at_decimal_bounds() {
+        let mut account = Account::default();
+        let tx = |_type, tx, amount| Transaction::new(_type, 1, tx, amount);
+        let mut max_deposit = tx(TransactionType::Deposit, 1, Some(MAX_BALANCE));
+        let mut max_withdrawal = tx(TransactionType::Withdrawal, 2, Some(MAX_BALANCE));
+        let mut other_deposit = tx(TransactionType::Deposit, 3, Some(MAX_BALANCE));
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::Overflow),
+            account.apply_tx(&tx(TransactionType::Deposit, 4, Some(Decimal::MAX)), None)
+        );
+        assert_eq!(Account::default(), account);
+
+        assert_eq!(TxOutcome::Applied, account.apply_tx(&max_deposit, None));
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::Overflow),
+            account.apply_tx(&tx(TransactionType::Deposit, 5, Some(dec!(0.0001))), None)
+        );
+        assert_eq!(MAX_BALANCE, account.available);
+        assert_eq!(MAX_BALANCE, account.total);
+
+        // a disputed withdrawal is a pending credit on top of the balance
+        assert_eq!(TxOutcome::Applied, account.apply_tx(&max_withdrawal, None));
+        assert_eq!(TxOutcome::Applied, account.apply_tx(&other_deposit, None));
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::Overflow),
+            account.apply_tx(
+                &tx(TransactionType::Dispute, 2, None),
+                Some(&mut max_withdrawal)
+            )
+        );
+        assert_eq!(TransactionState::Open, max_withdrawal.state);
+        assert_eq!(DisputedAmounts::default(), max_withdrawal.disputes);
+
+        // disputing both deposits would take available below `-MAX_BALANCE`
+        account.apply_tx(&tx(TransactionType::Withdrawal, 6, Some(MAX_BALANCE)), None);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(
+                &tx(TransactionType::Dispute, 1, None),
+                Some(&mut max_deposit)
+            )
+        );
+        assert_eq!(-MAX_BALANCE, account.available);
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::Overflow),
+            account.apply_tx(
+                &tx(TransactionType::Dispute, 3, None),
+                Some(&mut other_deposit)
+            )
+        );
+        assert_eq!(-MAX_BALANCE, account.available);
+        assert_eq!(MAX_BALANCE, account.held);
+        assert_eq!(Decimal::ZERO, account.total);
+        assert_eq!(TransactionState::Open, other_deposit.state);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn exact_at_max_balance() {
+        let mut account = Account::default();
+        let deposit = |tx, amount| Transaction::new(TransactionType::Deposit, 1, tx, Some(amount));
+        let whole = MAX_BALANCE.trunc() - Decimal::ONE;
+
+        // adding these at full `Decimal` range would round the fraction away
+        account.apply_tx(&deposit(1, whole), None);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&deposit(2, dec!(0.5)), None)
+        );
+        assert_eq!(whole + dec!(0.5), account.total);
+        assert_eq!(dec!(0.5), account.total.fract());
+    }
+
+    #[test]
+    fn rejection_reasons() {
+        let mut account = Account::default();
+
+        let mut deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let zero_deposit = Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::ZERO));
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 3, Some(dec!(20)));
+
+        assert_eq!(TxOutcome::Applied, account.apply_tx(&deposit, None));
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::ZeroAmount),
+            account.apply_tx(&zero_deposit, None)
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::InsufficientFunds),
+            account.apply_tx(&withdrawal, None)
+        );
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 1, None);
+
+        let mut not_disputable = resolve_tx;
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::UnknownTx),
+            account.apply_tx(&dispute_tx, None)
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::NotDisputable),
+            account.apply_tx(&dispute_tx, Some(&mut not_disputable))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::NotDisputed),
+            account.apply_tx(&resolve_tx, Some(&mut deposit))
+        );
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&dispute_tx, Some(&mut deposit))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyDisputed),
+            account.apply_tx(&dispute_tx, Some(&mut deposit))
+        );
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&chargeback_tx, Some(&mut deposit))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyChargedBack),
+            account.apply_tx(&dispute_tx, Some(&mut deposit))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AccountLocked),
+            account.apply_tx(&withdrawal, None)
+        );
+    }
+
+    #[test]
+    fn dispute_txs() {
+        let mut account = Account::default();
+
+        let mut tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(5)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+
+        account.apply_tx(&dispute_tx, None);
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        let mut not_disputable = dispute_tx;
+
+        account.apply_tx(&dispute_tx, Some(&mut not_disputable));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::Open, not_disputable.state);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx1.state);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx1.state);
+    }
+
+    #[test]
+    fn resolve_tx() {
+        let mut account = Account::default();
+
+        let mut tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(5)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx1.state);
+
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 1, None);
+
+        let mut not_disputable = dispute_tx;
+
+        not_disputable.state = TransactionState::ActiveDispute;
+        account.apply_tx(&resolve_tx, Some(&mut not_disputable));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+
+        account.apply_tx(&resolve_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::Open, tx1.state);
+
+        account.apply_tx(&resolve_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::Open, tx1.state);
+    }
+
+    #[test]
+    fn chargeback_tx() {
+        let mut account = Account::default();
+
+        let mut tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(5)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert!(!account.locked);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx1.state);
+
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 1, None);
+
+        let mut not_disputable = dispute_tx;
+
+        not_disputable.state = TransactionState::ActiveDispute;
+        account.apply_tx(&chargeback_tx, Some(&mut not_disputable));
+
+        assert_eq!(dec!(5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(dec!(10), account.held);
+
+        account.apply_tx(&chargeback_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(-5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert!(account.locked);
+        assert_eq!(TransactionState::ChargedBack, tx1.state);
+
+        account.apply_tx(&chargeback_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(-5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert!(account.locked);
+        assert_eq!(TransactionState::ChargedBack, tx1.state);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx1));
+        account.apply_tx(&chargeback_tx, Some(&mut tx1));
+
+        assert_eq!(dec!(-5), account.total);
+        assert_eq!(dec!(-5), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert!(account.locked);
+        assert_eq!(TransactionState::ChargedBack, tx1.state);
+    }
+
+    #[test]
+    fn dispute_withdrawal_tx() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let mut tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&dispute_tx, Some(&mut tx2))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(6), account.available);
+        assert_eq!(dec!(4), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx2.state);
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyDisputed),
+            account.apply_tx(&dispute_tx, Some(&mut tx2))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(6), account.available);
+        assert_eq!(dec!(4), account.held);
+    }
+
+    #[test]
+    fn resolve_withdrawal_tx() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let mut tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx2));
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&resolve_tx, Some(&mut tx2))
+        );
+        assert_eq!(dec!(6), account.total);
+        assert_eq!(dec!(6), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::Open, tx2.state);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn chargeback_withdrawal_tx() {
+        let mut account = Account::default();
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+        let mut tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4)));
+
+        account.apply_tx(&tx1, None);
+        account.apply_tx(&tx2, None);
+
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 2, None);
+
+        account.apply_tx(&dispute_tx, Some(&mut tx2));
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&chargeback_tx, Some(&mut tx2))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(10), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::ChargedBack, tx2.state);
+        assert!(account.locked);
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyChargedBack),
+            account.apply_tx(&dispute_tx, Some(&mut tx2))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(10), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+    }
+
+    #[test]
+    fn partial_dispute_txs() {
+        let mut account = Account::default();
+
+        let mut tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+
+        account.apply_tx(&tx1, None);
+
+        let dispute_tx = |amount| Transaction::new(TransactionType::Dispute, 1, 1, amount);
+        let resolve_tx = |amount| Transaction::new(TransactionType::Resolve, 1, 1, amount);
+        let chargeback_tx = |amount| Transaction::new(TransactionType::Chargeback, 1, 1, amount);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&dispute_tx(Some(dec!(4))), Some(&mut tx1))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(6), account.available);
+        assert_eq!(dec!(4), account.held);
+        assert_eq!(TransactionState::ActiveDispute, tx1.state);
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::ExceedsDisputable),
+            account.apply_tx(&dispute_tx(Some(dec!(7))), Some(&mut tx1))
+        );
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&dispute_tx(Some(dec!(6))), Some(&mut tx1))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyDisputed),
+            account.apply_tx(&dispute_tx(None), Some(&mut tx1))
+        );
+        assert_eq!(Decimal::ZERO, account.available);
+        assert_eq!(dec!(10), account.held);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&resolve_tx(Some(dec!(3))), Some(&mut tx1))
+        );
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::ExceedsDisputed),
+            account.apply_tx(&resolve_tx(Some(dec!(8))), Some(&mut tx1))
+        );
+        assert_eq!(dec!(10), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(dec!(7), account.held);
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&chargeback_tx(Some(dec!(2))), Some(&mut tx1))
+        );
+        assert_eq!(dec!(8), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(dec!(5), account.held);
+        assert!(account.locked);
+        assert_eq!(
+            DisputedAmounts {
+                disputed: dec!(5),
+                resolved: dec!(3),
+                charged_back: dec!(2),
+            },
+            tx1.disputes
+        );
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&chargeback_tx(None), Some(&mut tx1))
+        );
+        assert_eq!(dec!(3), account.total);
+        assert_eq!(dec!(3), account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::Open, tx1.state);
+        assert_eq!(dec!(3), tx1.disputable());
+
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&dispute_tx(None), Some(&mut tx1))
+        );
+        assert_eq!(
+            TxOutcome::Applied,
+            account.apply_tx(&chargeback_tx(None), Some(&mut tx1))
+        );
+        assert_eq!(Decimal::ZERO, account.total);
+        assert_eq!(Decimal::ZERO, account.available);
+        assert_eq!(Decimal::ZERO, account.held);
+        assert_eq!(TransactionState::ChargedBack, tx1.state);
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::AlreadyChargedBack),
+            account.apply_tx(&dispute_tx(None), Some(&mut tx1))
+        );
+    }
+}
+
+// optimizations
+//
+// -- serde allocation on every loop --
+// test tests::medium ... bench: 205,058,347 ns/iter (+/- 14,764,710)
+// test tests::small  ... bench:      96,385 ns/iter (+/- 4,975)
+//
+// -- zero serde allocations --
+// no speed up at all - after looking at the flamegraph it's apparent the majority of the time is
+// spend in csv::trim and csv::StringRecord
+//
+// -- serde allocation + moving f32 to Decimal - final version
+// test tests::medium ... bench: 231,653,952 ns/iter (+/- 18,252,282)
+// test tests::small  ... bench:      46,188 ns/iter (+/- 12,031)
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// The result of applying a single transaction. A rejected transaction leaves the ledger
+/// untouched.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxOutcome {
+    Applied,
+    Rejected(TxRejection),
+}
+
+impl TxOutcome {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, Self::Applied)
+    }
+}
+
+/// The reason a transaction was not applied. Serializes to a snake case reason code.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRejection {
+    /// Negative amount or an amount with more than four places after the decimal.
+    InvalidData,
+    /// Deposits and withdrawals are not allowed on a locked account.
+    AccountLocked,
+    /// Withdrawal amount exceeds the available funds.
+    InsufficientFunds,
+    /// Missing or zero amount on a deposit or withdrawal, a zero amount on a dispute, resolve or
+    /// chargeback, or a referenced tx with a zero amount.
+    ZeroAmount,
+    /// The referenced tx does not exist.
+    UnknownTx,
+    /// The referenced tx belongs to a different client.
+    ClientMismatch,
+    /// The referenced tx is of a type that cannot be disputed.
+    NotDisputable,
+    /// A dispute on a tx whose whole amount is already actively disputed.
+    AlreadyDisputed,
+    /// A dispute amount larger than what is still disputable on the referenced tx.
+    ExceedsDisputable,
+    /// A resolve or chargeback on a tx that is not actively disputed.
+    NotDisputed,
+    /// A resolve or chargeback amount larger than what is actively disputed on the referenced tx.
+    ExceedsDisputed,
+    /// Any dispute, resolve, or chargeback on a tx whose whole amount has been charged back.
+    AlreadyChargedBack,
+    /// Applying the transaction would take a balance beyond `MAX_BALANCE`.
+    Overflow,
+    /// A deposit or withdrawal reusing the tx id of an earlier one, when duplicates don't abort
+    /// the run.
+    DuplicateTx,
+    /// A dispute timestamped later after the referenced tx than the dispute window allows.
+    DisputeWindowExpired,
+}
@@ -0,0 +1,154 @@
+use std::{
+    io::{self, BufWriter, Write},
+    str::FromStr,
+};
+
+use crate::{account::Account, error::EngineError, transaction::DECIMAL_PRECISION};
+
+/// The format accounts are written in. Every format has the `client`, `available`, `held`,
+/// `total` and `locked` fields, with balances rendered as strings with four decimal places to
+/// keep their precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of account objects.
+    Json,
+    /// Newline delimited JSON, one account object per line.
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => Err(format!(
+                "Unknown output format {s}, expected csv, json or jsonl"
+            )),
+        }
+    }
+}
+
+/// Writes `accounts` to `output` in the given format. Every format is buffered here, CSV by the
+/// csv writer and JSON by a `BufWriter`, so `output` doesn't need to be.
+pub fn write_accounts<'a, W, I>(
+    output: W,
+    format: OutputFormat,
+    accounts: I,
+) -> Result<(), EngineError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = &'a Account>,
+{
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new().from_writer(output);
+
+            for account in accounts {
+                wtr.serialize(rescaled(account))?;
+            }
+
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            let mut output = BufWriter::new(output);
+            let accounts: Vec<_> = accounts.into_iter().map(rescaled).collect();
+
+            serde_json::to_writer(&mut output, &accounts).map_err(io::Error::from)?;
+            writeln!(output)?;
+            output.flush()?;
+        }
+        OutputFormat::Jsonl => {
+            let mut output = BufWriter::new(output);
+
+            for account in accounts {
+                serde_json::to_writer(&mut output, &rescaled(account)).map_err(io::Error::from)?;
+                writeln!(output)?;
+            }
+
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns `account` with every balance at four decimal places. Balances otherwise keep the
+/// largest scale of the amounts that made them, so `2.0` and `2` would print differently.
+fn rescaled(account: &Account) -> Account {
+    let mut account = *account;
+
+    // valid amounts have at most four places, so this only ever adds zeroes
+    account.available.rescale(DECIMAL_PRECISION);
+    account.held.rescale(DECIMAL_PRECISION);
+    account.total.rescale(DECIMAL_PRECISION);
+
+    account
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::ClientId;
+
+    fn write(format: OutputFormat) -> String {
+        let accounts = [
+            Account {
+                client: ClientId(1u16),
+                available: dec!(0.5),
+                held: dec!(1.0111),
+                total: dec!(1.5111),
+                locked: false,
+            },
+            Account {
+                client: ClientId(2u16),
+                available: Decimal::ZERO,
+                held: Decimal::ZERO,
+                total: Decimal::ZERO,
+                locked: true,
+            },
+        ];
+        let mut output = vec![];
+
+        write_accounts(&mut output, format, &accounts).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_ser() {
+        let expected = r#"client,available,held,total,locked
+1,0.5000,1.0111,1.5111,false
+2,0.0000,0.0000,0.0000,true
+"#;
+
+        assert_eq!(expected, write(OutputFormat::Csv));
+    }
+
+    #[test]
+    fn json_ser() {
+        let expected = concat!(
+            r#"[{"client":1,"available":"0.5000","held":"1.0111","total":"1.5111","locked":false},"#,
+            r#"{"client":2,"available":"0.0000","held":"0.0000","total":"0.0000","locked":true}]"#,
+            "\n"
+        );
+
+        assert_eq!(expected, write(OutputFormat::Json));
+    }
+
+    #[test]
+    fn jsonl_ser() {
+        let expected = r#"{"client":1,"available":"0.5000","held":"1.0111","total":"1.5111","locked":false}
+{"client":2,"available":"0.0000","held":"0.0000","total":"0.0000","locked":true}
+"#;
+
+        assert_eq!(expected, write(OutputFormat::Jsonl));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::transaction::{ClientId, TxId};
+
+/// Ids per page, the ones sharing their upper 16 bits.
+const PAGE_IDS: usize = 1 << 16;
+const PAGE_WORDS: usize = PAGE_IDS / 64;
+/// The most ids a page keeps as a sorted list. Past this a list is larger than the page's arrays,
+/// so it's switched over.
+const LIST_LEN: usize = PAGE_IDS / 2;
+
+/// The owners of a page's ids, by their lower 16 bits.
+#[derive(Debug)]
+enum Page {
+    List(Vec<(u16, ClientId)>),
+    Array(Box<Clients>),
+}
+
+/// Every id of a page, with a bit for whether its owner is known, next to the owners themselves.
+#[derive(Debug)]
+struct Clients {
+    known: [u64; PAGE_WORDS],
+    clients: Box<[ClientId; PAGE_IDS]>,
+}
+
+/// The client that claimed each tx id, over the whole `u32` space. Pages are laid out as in
+/// `SeenTxs`, so a dense run of ids costs a client and a bit each and scattered ids cost a list
+/// entry each, rather than a map entry for every id.
+#[derive(Debug, Default)]
+pub(crate) struct TxOwners {
+    pages: Vec<Option<Page>>,
+}
+
+impl TxOwners {
+    /// Records `client` as the owner of `tx`, replacing any earlier owner.
+    pub(crate) fn insert(&mut self, tx: TxId, client: ClientId) {
+        let (page, id) = Self::locate(tx);
+
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, || None);
+        }
+
+        let page = self.pages[page].get_or_insert_with(|| Page::List(Vec::new()));
+
+        if let Page::List(ids) = page {
+            match ids.binary_search_by_key(&id, |(id, _)| *id) {
+                Ok(index) => {
+                    ids[index].1 = client;
+
+                    return;
+                }
+                Err(index) if ids.len() < LIST_LEN => {
+                    ids.insert(index, (id, client));
+
+                    return;
+                }
+                Err(_) => {}
+            }
+
+            let mut clients = Self::clients();
+
+            for (id, client) in ids.drain(..) {
+                clients.insert(id, client);
+            }
+
+            *page = Page::Array(clients);
+        }
+
+        let Page::Array(clients) = page else {
+            unreachable!("lists are either inserted into or switched to arrays above");
+        };
+
+        clients.insert(id, client);
+    }
+
+    /// Returns the client that claimed `tx`, if it's known.
+    pub(crate) fn get(&self, tx: TxId) -> Option<ClientId> {
+        let (page, id) = Self::locate(tx);
+
+        match self.pages.get(page)?.as_ref()? {
+            Page::List(ids) => {
+                let index = ids.binary_search_by_key(&id, |(id, _)| *id).ok()?;
+
+                Some(ids[index].1)
+            }
+            Page::Array(clients) => clients.get(id),
+        }
+    }
+
+    /// Allocates empty arrays on the heap, without building the clients on the stack first.
+    fn clients() -> Box<Clients> {
+        let clients = vec![ClientId::default(); PAGE_IDS].into_boxed_slice();
+
+        Box::new(Clients {
+            known: [0; PAGE_WORDS],
+            clients: clients.try_into().expect("PAGE_IDS clients"),
+        })
+    }
+
+    fn locate(tx: TxId) -> (usize, u16) {
+        // a u32 always fits in a usize on the targets this builds for, and the cast to u16 keeps
+        // exactly the lower 16 bits
+        ((tx.0 >> 16) as usize, tx.0 as u16)
+    }
+}
+
+impl Clients {
+    fn insert(&mut self, id: u16, client: ClientId) {
+        let (word, bit) = Self::bit(id);
+
+        self.known[word] |= bit;
+        self.clients[usize::from(id)] = client;
+    }
+
+    fn get(&self, id: u16) -> Option<ClientId> {
+        let (word, bit) = Self::bit(id);
+
+        (self.known[word] & bit != 0).then(|| self.clients[usize::from(id)])
+    }
+
+    fn bit(id: u16) -> (usize, u64) {
+        (usize::from(id) / 64, 1 << (id % 64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_get() {
+        let mut owners = TxOwners::default();
+        let txs = [0u32, 1, 65_535, 65_536, 1_000_000, u32::MAX];
+
+        for (client, tx) in txs.into_iter().enumerate() {
+            let client = ClientId(client as u16 + 1);
+
+            assert_eq!(None, owners.get(TxId(tx)), "{tx}");
+            owners.insert(TxId(tx), client);
+            assert_eq!(Some(client), owners.get(TxId(tx)), "{tx}");
+        }
+
+        for tx in [2u32, 65_537, 999_999, u32::MAX - 1] {
+            assert_eq!(None, owners.get(TxId(tx)), "{tx}");
+        }
+
+        owners.insert(TxId(1u32), ClientId(9u16));
+
+        assert_eq!(Some(ClientId(9u16)), owners.get(TxId(1u32)));
+        // only the pages holding an inserted id are allocated
+        assert_eq!(4, owners.pages.iter().flatten().count());
+    }
+
+    #[test]
+    fn list_becomes_array() {
+        let mut owners = TxOwners::default();
+        // the start of the second page, in reverse, so the list is inserted into at the front
+        let txs: Vec<_> = (0..=LIST_LEN as u32)
+            .rev()
+            .map(|id| (1 << 16) + id)
+            .collect();
+        let client = |tx: u32| ClientId(tx as u16 ^ 0x5555);
+
+        for tx in &txs[..LIST_LEN] {
+            owners.insert(TxId(*tx), client(*tx));
+        }
+
+        assert!(matches!(owners.pages[1], Some(Page::List(_))));
+        owners.insert(TxId(txs[LIST_LEN]), client(txs[LIST_LEN]));
+        assert!(matches!(owners.pages[1], Some(Page::Array(_))));
+
+        for tx in &txs {
+            assert_eq!(Some(client(*tx)), owners.get(TxId(*tx)), "{tx}");
+        }
+
+        for tx in [(1 << 16) + LIST_LEN as u32 + 1, (2 << 16) - 1] {
+            assert_eq!(None, owners.get(TxId(tx)), "{tx}");
+        }
+    }
+}
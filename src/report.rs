@@ -0,0 +1,44 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    outcome::TxRejection,
+    transaction::{ClientId, Transaction, TransactionType, TxId},
+};
+
+/// A row of the rejected transaction report. It carries the line the transaction was read from,
+/// the original fields of the transaction and the reason it was rejected. A `duplicate_tx` also
+/// carries the line of the deposit or withdrawal whose tx id it reused, unless that was read in an
+/// earlier run and restored from a snapshot, as its line belongs to another input.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RejectedTx {
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub _type: TransactionType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<Decimal>,
+    pub reason: TxRejection,
+    pub original_line: Option<u64>,
+}
+
+impl RejectedTx {
+    pub fn new(line: u64, tx: &Transaction, reason: TxRejection) -> Self {
+        Self {
+            line,
+            _type: tx._type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            reason,
+            original_line: None,
+        }
+    }
+
+    pub fn duplicate(line: u64, tx: &Transaction, original_line: Option<u64>) -> Self {
+        Self {
+            original_line,
+            ..Self::new(line, tx, TxRejection::DuplicateTx)
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::transaction::TxId;
+
+/// Ids per page, the ones sharing their upper 16 bits.
+const PAGE_IDS: usize = 1 << 16;
+const PAGE_WORDS: usize = PAGE_IDS / 64;
+/// The most ids a page keeps as a sorted list. Past this a list is larger than the page's arrays,
+/// so it's switched over.
+const LIST_LEN: usize = PAGE_IDS / 3;
+
+/// The ids of a page, by their lower 16 bits, along with the line each was claimed on, if known.
+#[derive(Debug)]
+enum Page {
+    List(Vec<(u16, Option<u64>)>),
+    Array(Box<Lines>),
+}
+
+/// Every id of a page, with a bit for whether it's claimed and another for whether its line is
+/// known, next to the lines themselves.
+#[derive(Debug)]
+struct Lines {
+    claimed: [u64; PAGE_WORDS],
+    known: [u64; PAGE_WORDS],
+    lines: Box<[u64; PAGE_IDS]>,
+}
+
+/// A set of tx ids over the whole `u32` space, each with the line it was claimed on when that's
+/// known. Each page of ids starts out as a sorted list and becomes a set of arrays once it fills
+/// up, so a dense run of ids costs a line and two bits each and scattered ids cost a list entry
+/// each. Pages are only allocated once an id inside them is inserted.
+#[derive(Debug, Default)]
+pub(crate) struct SeenTxs {
+    pages: Vec<Option<Page>>,
+}
+
+impl SeenTxs {
+    /// Adds `tx`, claimed on `line`, to the set, returning whether it wasn't already present. An
+    /// id that's already present keeps the line it was first claimed on.
+    pub(crate) fn insert(&mut self, tx: TxId, line: Option<u64>) -> bool {
+        let (page, id) = Self::locate(tx);
+
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, || None);
+        }
+
+        let page = self.pages[page].get_or_insert_with(|| Page::List(Vec::new()));
+
+        if let Page::List(ids) = page {
+            let Err(index) = ids.binary_search_by_key(&id, |(id, _)| *id) else {
+                return false;
+            };
+
+            if ids.len() < LIST_LEN {
+                ids.insert(index, (id, line));
+
+                return true;
+            }
+
+            let mut lines = Self::lines();
+
+            for (id, line) in ids.drain(..) {
+                lines.insert(id, line);
+            }
+
+            *page = Page::Array(lines);
+        }
+
+        let Page::Array(lines) = page else {
+            unreachable!("lists are either inserted into or switched to arrays above");
+        };
+
+        if lines.contains(id) {
+            return false;
+        }
+
+        lines.insert(id, line);
+
+        true
+    }
+
+    pub(crate) fn contains(&self, tx: TxId) -> bool {
+        let (page, id) = Self::locate(tx);
+
+        match self.pages.get(page) {
+            Some(Some(Page::List(ids))) => ids.binary_search_by_key(&id, |(id, _)| *id).is_ok(),
+            Some(Some(Page::Array(lines))) => lines.contains(id),
+            Some(None) | None => false,
+        }
+    }
+
+    /// Returns the line `tx` was claimed on, if it's present and its line is known.
+    pub(crate) fn line(&self, tx: TxId) -> Option<u64> {
+        let (page, id) = Self::locate(tx);
+
+        match self.pages.get(page)?.as_ref()? {
+            Page::List(ids) => {
+                let index = ids.binary_search_by_key(&id, |(id, _)| *id).ok()?;
+
+                ids[index].1
+            }
+            Page::Array(lines) => lines.line(id),
+        }
+    }
+
+    /// Returns every id in the set in order, along with its line.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (TxId, Option<u64>)> + '_ {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(page, ids)| Some((page, ids.as_ref()?)))
+            .flat_map(|(page, ids)| {
+                let ids: Box<dyn Iterator<Item = (u16, Option<u64>)>> = match ids {
+                    Page::List(ids) => Box::new(ids.iter().copied()),
+                    Page::Array(lines) => Box::new(
+                        (0..=u16::MAX)
+                            .filter(|id| lines.contains(*id))
+                            .map(|id| (id, lines.line(id))),
+                    ),
+                };
+
+                // only ever called with a page `locate` gave for a u32
+                ids.map(move |(id, line)| (TxId((page as u32) << 16 | u32::from(id)), line))
+            })
+    }
+
+    /// Allocates empty arrays on the heap, without building the lines on the stack first.
+    fn lines() -> Box<Lines> {
+        let lines = vec![0; PAGE_IDS].into_boxed_slice();
+
+        Box::new(Lines {
+            claimed: [0; PAGE_WORDS],
+            known: [0; PAGE_WORDS],
+            lines: lines.try_into().expect("PAGE_IDS lines"),
+        })
+    }
+
+    fn locate(tx: TxId) -> (usize, u16) {
+        // a u32 always fits in a usize on the targets this builds for, and the cast to u16 keeps
+        // exactly the lower 16 bits
+        ((tx.0 >> 16) as usize, tx.0 as u16)
+    }
+}
+
+impl Lines {
+    fn insert(&mut self, id: u16, line: Option<u64>) {
+        let (word, bit) = Self::bit(id);
+
+        self.claimed[word] |= bit;
+
+        if let Some(line) = line {
+            self.known[word] |= bit;
+            self.lines[usize::from(id)] = line;
+        }
+    }
+
+    fn contains(&self, id: u16) -> bool {
+        let (word, bit) = Self::bit(id);
+
+        self.claimed[word] & bit != 0
+    }
+
+    fn line(&self, id: u16) -> Option<u64> {
+        let (word, bit) = Self::bit(id);
+
+        (self.known[word] & bit != 0).then(|| self.lines[usize::from(id)])
+    }
+
+    fn bit(id: u16) -> (usize, u64) {
+        (usize::from(id) / 64, 1 << (id % 64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_contains() {
+        let mut seen = SeenTxs::default();
+        let txs = [
+            0u32,
+            1,
+            63,
+            64,
+            65_535,
+            65_536,
+            1_000_000,
+            u32::MAX - 1,
+            u32::MAX,
+        ];
+
+        for (line, tx) in txs.into_iter().enumerate() {
+            let line = Some(line as u64 + 2);
+
+            assert!(!seen.contains(TxId(tx)), "{tx}");
+            assert!(seen.insert(TxId(tx), line), "{tx}");
+            assert!(seen.contains(TxId(tx)), "{tx}");
+            assert!(!seen.insert(TxId(tx), Some(99)), "{tx}");
+            assert_eq!(line, seen.line(TxId(tx)), "{tx}");
+        }
+
+        for tx in [2u32, 62, 65_537, 999_999, u32::MAX - 2] {
+            assert!(!seen.contains(TxId(tx)), "{tx}");
+            assert_eq!(None, seen.line(TxId(tx)), "{tx}");
+        }
+
+        assert!(seen.insert(TxId(2u32), None));
+        assert!(seen.contains(TxId(2u32)));
+        assert_eq!(None, seen.line(TxId(2u32)));
+
+        // only the pages holding an inserted id are allocated
+        assert_eq!(4, seen.pages.iter().flatten().count());
+        assert_eq!(
+            vec![(0, Some(2)), (1, Some(3)), (2, None), (63, Some(4))],
+            seen.iter()
+                .take(4)
+                .map(|(tx, line)| (tx.0, line))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(txs.len() + 1, seen.iter().count());
+    }
+
+    #[test]
+    fn list_becomes_array() {
+        let mut seen = SeenTxs::default();
+        // every third id of the second page, in reverse, so the list is inserted into at the front
+        let txs: Vec<_> = (0..=LIST_LEN as u32)
+            .rev()
+            .map(|id| (1 << 16) + id * 3)
+            .collect();
+        // every other id comes without a line
+        let line = |tx: u32| tx.is_multiple_of(2).then_some(u64::from(tx) * 10);
+
+        for tx in &txs[..LIST_LEN] {
+            assert!(seen.insert(TxId(*tx), line(*tx)), "{tx}");
+        }
+
+        assert!(matches!(seen.pages[1], Some(Page::List(_))));
+        assert!(seen.insert(TxId(txs[LIST_LEN]), line(txs[LIST_LEN])));
+        assert!(matches!(seen.pages[1], Some(Page::Array(_))));
+
+        for tx in &txs {
+            assert!(seen.contains(TxId(*tx)), "{tx}");
+            assert!(!seen.insert(TxId(*tx), Some(1)), "{tx}");
+            assert_eq!(line(*tx), seen.line(TxId(*tx)), "{tx}");
+            assert!(!seen.contains(TxId(tx + 1)), "{tx}");
+        }
+
+        let mut sorted: Vec<_> = txs.iter().map(|tx| (*tx, line(*tx))).collect();
+
+        sorted.sort_unstable();
+
+        assert_eq!(
+            sorted,
+            seen.iter()
+                .map(|(tx, line)| (tx.0, line))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    io, mem,
+    num::NonZeroUsize,
+    panic,
+    sync::mpsc,
+    thread::{self, ScopedJoinHandle},
+};
+
+use crate::{
+    engine::Engine,
+    error::EngineError,
+    input::{self, InputFormat},
+    outcome::TxOutcome,
+    owners::TxOwners,
+    report::RejectedTx,
+    transaction::{ClientId, Transaction},
+};
+
+/// Transactions are handed to workers in batches so the channel isn't touched for every row.
+const BATCH_SIZE: usize = 1024;
+/// The number of batches a worker can fall behind before the reader waits for it.
+const QUEUED_BATCHES: usize = 16;
+
+type Batch = Vec<(u64, Transaction)>;
+
+/// What a worker hands back once its input is exhausted.
+struct Shard {
+    engine: Engine,
+    rejected: Vec<RejectedTx>,
+}
+
+/// Processes the transactions read from `input` through `engine` on `threads` worker threads and
+/// returns the engine once every transaction is applied.
+///
+/// Clients are independent of each other, so `engine` is split into one engine per worker, each
+/// owning the ledger and history of the clients `shard` assigns to it. The reading thread screens
+/// every transaction first, which is what keeps tx ids globally unique. It also knows which client
+/// claimed each tx id, so a reference to another client's tx goes to the worker holding that tx,
+/// which rejects it without touching either account. The accounts and rejected transactions are
+/// therefore identical to a single threaded run. Rejected transactions are collected while the
+/// workers run and written to `rejected` ordered by line at the end.
+pub(crate) fn process<R, W>(
+    engine: Engine,
+    input: R,
+    format: InputFormat,
+    threads: NonZeroUsize,
+    rejected: Option<&mut csv::Writer<W>>,
+) -> Result<Engine, EngineError>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let report = rejected.is_some();
+    // the client of every claimed tx id, since the history it's otherwise read from is split
+    // between the workers
+    let mut owners = TxOwners::default();
+
+    for (tx, record) in engine.store().transactions.iter() {
+        owners.insert(tx, record.client);
+    }
+
+    let (mut screen, engines) = engine.into_shards(threads);
+    let mut screened = Vec::new();
+
+    let (read, shards) = thread::scope(|scope| {
+        let (senders, workers): (Vec<_>, Vec<_>) = engines
+            .into_iter()
+            .map(|engine| {
+                let (sender, receiver) = mpsc::sync_channel(QUEUED_BATCHES);
+                let worker = scope.spawn(move || work(engine, receiver, report));
+
+                (sender, worker)
+            })
+            .unzip();
+        let mut batches = vec![Batch::with_capacity(BATCH_SIZE); threads.get()];
+
+        let read = input::read_txs(input, format, |line, tx| {
+            if let Some(reason) = screen.screen(line, &tx)? {
+                if report {
+                    let Ok(rejection) = screen.rejection(line, &tx, reason);
+
+                    screened.extend(rejection);
+                }
+
+                return Ok(());
+            }
+
+            let owner = match tx.requires_unique_tx() {
+                true => {
+                    owners.insert(tx.tx, tx.client);
+
+                    tx.client
+                }
+                false => owners.get(tx.tx).unwrap_or(tx.client),
+            };
+            let shard = shard(owner, threads);
+            let batch = &mut batches[shard];
+
+            batch.push((line, tx));
+
+            if batch.len() == BATCH_SIZE {
+                senders[shard]
+                    .send(mem::replace(batch, Batch::with_capacity(BATCH_SIZE)))
+                    .map_err(io::Error::other)?;
+            }
+
+            Ok(())
+        });
+
+        // a worker only hangs up when it panics, which is resumed when it's joined below
+        for (sender, batch) in senders.into_iter().zip(batches) {
+            if !batch.is_empty() {
+                let _ = sender.send(batch);
+            }
+        }
+
+        let shards: Vec<_> = workers.into_iter().map(join).collect();
+
+        (read, shards)
+    });
+
+    let mut engines = Vec::with_capacity(shards.len());
+    let mut rejections = screened;
+
+    for mut shard in shards {
+        engines.push(shard.engine);
+        rejections.append(&mut shard.rejected);
+    }
+
+    if let Some(rejected) = rejected {
+        rejections.sort_unstable_by_key(|rejection| rejection.line);
+
+        for rejection in rejections {
+            rejected.serialize(rejection)?;
+        }
+    }
+
+    read.map(|()| Engine::from_shards(screen, engines))
+}
+
+/// Returns the index of the worker that owns `client`.
+pub(crate) fn shard(client: ClientId, threads: NonZeroUsize) -> usize {
+    usize::from(client.0) % threads.get()
+}
+
+fn work(mut engine: Engine, batches: mpsc::Receiver<Batch>, report: bool) -> Shard {
+    let mut rejected = Vec::new();
+
+    for batch in batches {
+        for (line, tx) in batch {
+            let Ok(outcome) = engine.apply(tx);
+
+            if let TxOutcome::Rejected(reason) = outcome {
+                if report {
+                    rejected.push(RejectedTx::new(line, &tx, reason));
+                }
+            }
+        }
+    }
+
+    Shard { engine, rejected }
+}
+
+fn join(worker: ScopedJoinHandle<Shard>) -> Shard {
+    worker
+        .join()
+        .unwrap_or_else(|panic| panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+    use crate::{
+        account::{Account, SortBy},
+        engine::DuplicatePolicy,
+    };
+
+    /// Builds enough transactions to fill several batches per worker, including cross client
+    /// references, invalid rows, reused tx ids and chargebacks that lock accounts.
+    fn transactions() -> String {
+        let mut actual = String::from("type,client,tx,amount\n");
+
+        for tx in 12..20_000u32 {
+            let client = tx / 120;
+            let row = match tx % 12 {
+                0 => format!("withdrawal,{client},{tx},{}.25", tx % 7),
+                1 => format!("dispute,{client},{},", tx - 1),
+                2 => format!("dispute,{},{},", client + 1, tx - 5),
+                3 => format!("resolve,{client},{},", tx - 3),
+                4 => format!("deposit,{client},{tx},-1"),
+                5 => format!("dispute,{client},{},", tx - 8),
+                6 => format!("chargeback,{client},{},", tx - 9),
+                8 => format!("deposit,{},{},1.0", client + 1, tx - 1),
+                _ => format!("deposit,{client},{tx},{}.5", tx % 13),
+            };
+
+            writeln!(actual, "{row}").unwrap();
+        }
+
+        actual
+    }
+
+    fn single_threaded(input: &str) -> (Vec<Account>, String) {
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let mut engine = Engine::with_duplicates(DuplicatePolicy::Report);
+
+        crate::process(
+            &mut engine,
+            input.as_bytes(),
+            InputFormat::Csv,
+            0,
+            Some(&mut rejected),
+        )
+        .unwrap();
+
+        let accounts = engine.sorted_accounts(SortBy::Client);
+        let accounts = accounts.into_iter().copied().collect();
+
+        (
+            accounts,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap(),
+        )
+    }
+
+    fn multi_threaded(input: &str, threads: usize) -> (Vec<Account>, String) {
+        let mut rejected = csv::Writer::from_writer(vec![]);
+        let engine = process(
+            Engine::with_duplicates(DuplicatePolicy::Report),
+            input.as_bytes(),
+            InputFormat::Csv,
+            NonZeroUsize::new(threads).unwrap(),
+            Some(&mut rejected),
+        )
+        .unwrap();
+        let accounts = engine.sorted_accounts(SortBy::Client);
+        let accounts = accounts.into_iter().copied().collect();
+
+        (
+            accounts,
+            String::from_utf8(rejected.into_inner().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn matches_single_threaded() {
+        let input = transactions();
+        let expected = single_threaded(&input);
+
+        assert!(expected.0.iter().any(|account| account.locked));
+        assert!(expected.1.contains("client_mismatch"));
+        assert!(expected.1.contains("duplicate_tx"));
+
+        for threads in [1, 2, 3, 8] {
+            assert_eq!(expected, multi_threaded(&input, threads), "{threads}");
+        }
+    }
+
+    #[test]
+    fn references_across_shards() {
+        // client 2's dispute references a deposit of client 1, which lives on the other worker,
+        // and client 4's a rejected withdrawal of client 3
+        let actual = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,3,2,1.0\n\
+                      dispute,2,1,\ndispute,4,2,\n";
+        let (accounts, report) = multi_threaded(actual, 2);
+        let expected = "line,type,client,tx,amount,reason,original_line\n\
+                        3,withdrawal,3,2,1.0,insufficient_funds,\n\
+                        4,dispute,2,1,,client_mismatch,\n\
+                        5,dispute,4,2,,unknown_tx,\n";
+
+        assert_eq!(expected, report);
+        // neither rejected dispute creates an account
+        assert_eq!(
+            vec![ClientId(1u16), ClientId(3u16)],
+            accounts
+                .iter()
+                .map(|account| account.client)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!((accounts, report), single_threaded(actual));
+    }
+
+    #[test]
+    fn duplicate_across_shards() {
+        let actual = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,2,1,1.0\n";
+        let err = process(
+            Engine::new(),
+            actual.as_bytes(),
+            InputFormat::Csv,
+            NonZeroUsize::new(2).unwrap(),
+            None::<&mut csv::Writer<Vec<u8>>>,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            EngineError::DuplicateTx {
+                first: 2,
+                second: 3,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    io::{self, BufRead, BufReader},
+    iter,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    error::EngineError,
+    transaction::{
+        ClientId, DisputedAmounts, Timestamp, Transaction, TransactionState, TransactionType, TxId,
+    },
+};
+
+/// The snapshot format written by this version. Bumped whenever a field changes meaning or goes
+/// away, so an older engine refuses a snapshot it can't continue from exactly.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+/// The first line of every snapshot.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    version: u32,
+}
+
+/// A line of a snapshot after its header. A snapshot holds everything an `Engine` needs to continue
+/// where an earlier run stopped: every account, every stored deposit and withdrawal with its
+/// dispute state, and every claimed tx id, one per line as JSON with balances as strings so no
+/// precision is lost. It ends with `End`, so a snapshot that was cut short is refused rather than
+/// continued from.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Record {
+    Account(Account),
+    Tx(StoredTx),
+    Claim(StoredClaim),
+    End,
+}
+
+/// A deposit or withdrawal from the history, including the portions that are disputed, resolved
+/// and charged back.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct StoredTx {
+    #[serde(rename = "type")]
+    _type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    state: TransactionState,
+    disputed: Decimal,
+    resolved: Decimal,
+    charged_back: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+}
+
+/// A tx id claimed by a deposit or withdrawal, whether or not it was applied. The line it was
+/// claimed on isn't kept, as it's a line of an earlier run's input rather than the one that
+/// continues from the snapshot.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct StoredClaim {
+    pub(crate) tx: TxId,
+}
+
+/// Reads a snapshot written by `write`, refusing any other version, and hands every record to
+/// `restore` as soon as it's read, so the snapshot never has to fit in memory.
+pub(crate) fn read<R: io::Read>(
+    input: R,
+    mut restore: impl FnMut(Record) -> Result<(), EngineError>,
+) -> Result<(), EngineError> {
+    let mut lines = BufReader::new(input).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let Header { version } = serde_json::from_str(&header).map_err(snapshot_error)?;
+
+    if version != SNAPSHOT_VERSION {
+        return Err(EngineError::Snapshot {
+            message: format!("unsupported version {version}, expected {SNAPSHOT_VERSION}"),
+        });
+    }
+
+    let mut ended = false;
+
+    for line in lines {
+        let line = line?;
+
+        if ended {
+            return Err(EngineError::Snapshot {
+                message: "records after the end of the snapshot".to_string(),
+            });
+        }
+
+        match serde_json::from_str(&line).map_err(snapshot_error)? {
+            Record::End => ended = true,
+            record => restore(record)?,
+        }
+    }
+
+    match ended {
+        true => Ok(()),
+        false => Err(EngineError::Snapshot {
+            message: "the snapshot ends before its last record".to_string(),
+        }),
+    }
+}
+
+/// Writes a snapshot holding `records` to `output` one record at a time.
+pub(crate) fn write<W: io::Write>(
+    mut output: W,
+    records: impl Iterator<Item = Result<Record, EngineError>>,
+) -> Result<(), EngineError> {
+    let header = Header {
+        version: SNAPSHOT_VERSION,
+    };
+
+    serde_json::to_writer(&mut output, &header).map_err(io::Error::from)?;
+    output.write_all(b"\n")?;
+
+    for record in records.chain(iter::once(Ok(Record::End))) {
+        serde_json::to_writer(&mut output, &record?).map_err(io::Error::from)?;
+        output.write_all(b"\n")?;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+impl From<&Transaction> for StoredTx {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            _type: tx._type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            state: tx.state,
+            disputed: tx.disputes.disputed,
+            resolved: tx.disputes.resolved,
+            charged_back: tx.disputes.charged_back,
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+impl From<StoredTx> for Transaction {
+    fn from(tx: StoredTx) -> Self {
+        Self {
+            _type: tx._type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            state: tx.state,
+            disputes: DisputedAmounts {
+                disputed: tx.disputed,
+                resolved: tx.resolved,
+                charged_back: tx.charged_back,
+            },
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+fn snapshot_error(err: serde_json::Error) -> EngineError {
+    EngineError::Snapshot {
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        engine::DuplicatePolicy,
+        outcome::{TxOutcome, TxRejection},
+        report::RejectedTx,
+        store::{DiskStore, LedgerStore},
+        Engine,
+    };
+
+    /// An engine with a disputed deposit, a rejected withdrawal and a locked account.
+    fn engine() -> Engine {
+        let mut engine = Engine::with_duplicates(DuplicatePolicy::Report);
+
+        for tx in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10.5))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(dec!(3))),
+            Transaction::new(TransactionType::Withdrawal, 2, 3, Some(dec!(5))),
+            Transaction::new(TransactionType::Dispute, 1, 1, Some(dec!(2.25))),
+            Transaction::new(TransactionType::Deposit, 3, 4, Some(dec!(1))),
+            Transaction::new(TransactionType::Dispute, 3, 4, None),
+            Transaction::new(TransactionType::Chargeback, 3, 4, None),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        engine
+    }
+
+    fn snapshot<S: LedgerStore>(engine: &Engine<S>) -> String {
+        let mut bytes = Vec::new();
+
+        engine.write_snapshot(&mut bytes).unwrap();
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn restore(snapshot: &str) -> Result<Engine, EngineError> {
+        Engine::from_snapshot(snapshot.as_bytes(), DuplicatePolicy::Abort)
+    }
+
+    #[test]
+    fn round_trip() {
+        let expected = snapshot(&engine());
+        let restored = Engine::from_snapshot(expected.as_bytes(), DuplicatePolicy::Report).unwrap();
+
+        assert!(expected.starts_with("{\"version\":4}\n"));
+        assert!(expected.ends_with("\n\"end\"\n"));
+        assert_eq!(expected, snapshot(&restored));
+        assert!(restored.account(ClientId(3u16)).unwrap().locked);
+
+        let Ok(claimed) = restored.store().claimed(TxId(3u32));
+        let Ok(line) = restored.claimed_on(TxId(3u32));
+
+        assert!(claimed);
+        assert_eq!(None, line);
+    }
+
+    #[test]
+    fn disk_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("payments-engine-{}-snapshot", std::process::id()));
+        let expected = snapshot(&engine());
+        let restored = Engine::restore(
+            DiskStore::open(&dir).unwrap(),
+            expected.as_bytes(),
+            DuplicatePolicy::Report,
+        )
+        .unwrap();
+
+        assert_eq!(expected, snapshot(&restored));
+
+        drop(restored);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restored_claims_have_no_line() {
+        let mut engine =
+            Engine::from_snapshot(snapshot(&engine()).as_bytes(), DuplicatePolicy::Report).unwrap();
+        let duplicate = Transaction::new(TransactionType::Deposit, 2, 3, Some(dec!(1)));
+
+        assert_eq!(
+            TxOutcome::Rejected(TxRejection::DuplicateTx),
+            engine.process_line(2, duplicate).unwrap()
+        );
+        // the line the id was claimed on belongs to the snapshotted run's input
+        assert_eq!(
+            Some(RejectedTx::duplicate(2, &duplicate, None)),
+            engine
+                .rejection(2, &duplicate, TxRejection::DuplicateTx)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn continues_exactly() {
+        let mut engine = restore(&snapshot(&engine())).unwrap();
+
+        // the rejected withdrawal still owns its id
+        assert!(matches!(
+            engine.process(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                3,
+                Some(dec!(1))
+            )),
+            Err(EngineError::DuplicateRestoredTx { tx: TxId(3u32), .. })
+        ));
+
+        engine
+            .process(Transaction::new(
+                TransactionType::Resolve,
+                1,
+                1,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+
+        let account = engine.account(ClientId(1u16)).unwrap();
+
+        assert_eq!(dec!(9.25), account.available);
+        assert_eq!(dec!(1.25), account.held);
+        assert_eq!(dec!(10.5), account.total);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let valid = snapshot(&engine());
+        let other = valid.replace(r#"{"version":4}"#, r#"{"version":3}"#);
+
+        match restore(&other) {
+            Err(EngineError::Snapshot { message }) => {
+                assert_eq!("unsupported version 3, expected 4", message);
+            }
+            other => panic!("expected a snapshot error, got {other:?}"),
+        }
+
+        // cut short right after a record, which would otherwise read as a complete snapshot
+        let cut = &valid[..valid.len() - "\"end\"\n".len()];
+
+        match restore(cut) {
+            Err(EngineError::Snapshot { message }) => {
+                assert_eq!("the snapshot ends before its last record", message);
+            }
+            other => panic!("expected a snapshot error, got {other:?}"),
+        }
+
+        for invalid in [
+            format!("{valid}{valid}"),
+            "{\"version\":4}\n{\"account\":{}}\n\"end\"\n".to_string(),
+            "client,available".to_string(),
+            String::new(),
+        ] {
+            assert!(
+                matches!(restore(&invalid), Err(EngineError::Snapshot { .. })),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -0,0 +1,870 @@
+//! Where an `Engine` keeps its accounts, the deposits and withdrawals disputes can reference and
+//! the tx ids claimed so far.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    convert::Infallible,
+    io, iter,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    account::Account,
+    error::EngineError,
+    history::History,
+    seen::SeenTxs,
+    transaction::{ClientId, DisputedAmounts, HistoryRecord, RecordKind, TransactionState, TxId},
+};
+
+/// Storage for every client `Account`, the `HistoryRecord` of every stored deposit and withdrawal
+/// and every claimed tx id, keyed by client and tx id. Reads hand back copies, while the `_mut`
+/// variants give access to the stored value in place.
+pub trait LedgerStore {
+    type Error: Into<EngineError>;
+
+    fn account(&self, client: ClientId) -> Result<Option<Account>, Self::Error>;
+
+    fn account_mut(&mut self, client: ClientId) -> Result<Option<&mut Account>, Self::Error>;
+
+    fn insert_account(&mut self, account: Account) -> Result<(), Self::Error>;
+
+    fn tx(&self, tx: TxId) -> Result<Option<HistoryRecord>, Self::Error>;
+
+    fn tx_mut(&mut self, tx: TxId) -> Result<Option<&mut HistoryRecord>, Self::Error>;
+
+    fn insert_tx(&mut self, tx: TxId, record: HistoryRecord) -> Result<(), Self::Error>;
+
+    /// Returns whether a deposit or withdrawal has claimed `tx`.
+    fn claimed(&self, tx: TxId) -> Result<bool, Self::Error>;
+
+    /// Returns the line `tx` was claimed on, if it was claimed along with one.
+    fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, Self::Error>;
+
+    fn insert_claim(&mut self, tx: TxId, line: Option<u64>) -> Result<(), Self::Error>;
+
+    /// Returns every account in no particular order. There's at most one per client id, so they
+    /// always fit in memory.
+    fn accounts(&self) -> Result<Vec<Account>, Self::Error>;
+
+    /// Returns every stored record along with its tx id, in tx id order.
+    fn transactions(&self)
+        -> impl Iterator<Item = Result<(TxId, HistoryRecord), Self::Error>> + '_;
+
+    /// Returns every claimed tx id along with the line it was claimed on, if any, in tx id order.
+    fn claims(&self) -> impl Iterator<Item = Result<(TxId, Option<u64>), Self::Error>> + '_;
+
+    /// Marks a point where everything written so far belongs together: the end of the
+    /// transaction read from `line`, or of a record restored from a snapshot when it's `None`. A
+    /// store that persists across runs only writes its changes out at these points, so it's never
+    /// left halfway through a transaction.
+    fn checkpoint(&mut self, _line: Option<u64>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Returns the last input line the store already covers, when the run writing to it was
+    /// interrupted. That run is resumed after it.
+    fn checkpointed(&self) -> Option<u64> {
+        None
+    }
+
+    /// Writes every change out and marks the run as finished, so the next one starts on a new
+    /// input.
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Keeps everything in memory. This is the default store and the fastest one, as long as the
+/// history fits in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    pub(crate) accounts: HashMap<ClientId, Account>,
+    pub(crate) transactions: History,
+    pub(crate) claims: SeenTxs,
+}
+
+impl LedgerStore for MemoryStore {
+    type Error = Infallible;
+
+    fn account(&self, client: ClientId) -> Result<Option<Account>, Self::Error> {
+        Ok(self.accounts.get(&client).copied())
+    }
+
+    fn account_mut(&mut self, client: ClientId) -> Result<Option<&mut Account>, Self::Error> {
+        Ok(self.accounts.get_mut(&client))
+    }
+
+    fn insert_account(&mut self, account: Account) -> Result<(), Self::Error> {
+        self.accounts.insert(account.client, account);
+
+        Ok(())
+    }
+
+    fn tx(&self, tx: TxId) -> Result<Option<HistoryRecord>, Self::Error> {
+        Ok(self.transactions.get(tx).cloned())
+    }
+
+    fn tx_mut(&mut self, tx: TxId) -> Result<Option<&mut HistoryRecord>, Self::Error> {
+        Ok(self.transactions.get_mut(tx))
+    }
+
+    fn insert_tx(&mut self, tx: TxId, record: HistoryRecord) -> Result<(), Self::Error> {
+        self.transactions.insert(tx, record);
+
+        Ok(())
+    }
+
+    fn claimed(&self, tx: TxId) -> Result<bool, Self::Error> {
+        Ok(self.claims.contains(tx))
+    }
+
+    fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, Self::Error> {
+        Ok(self.claims.line(tx))
+    }
+
+    fn insert_claim(&mut self, tx: TxId, line: Option<u64>) -> Result<(), Self::Error> {
+        self.claims.insert(tx, line);
+
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, Self::Error> {
+        Ok(self.accounts.values().copied().collect())
+    }
+
+    fn transactions(
+        &self,
+    ) -> impl Iterator<Item = Result<(TxId, HistoryRecord), Self::Error>> + '_ {
+        self.transactions
+            .iter()
+            .map(|(tx, record)| Ok((tx, record.clone())))
+    }
+
+    fn claims(&self) -> impl Iterator<Item = Result<(TxId, Option<u64>), Self::Error>> + '_ {
+        self.claims.iter().map(Ok)
+    }
+}
+
+/// The number of accounts, transactions and claims held in memory before they're written to disk.
+const CACHE_CAPACITY: usize = 64 * 1024;
+
+// Every value lives in a single tree, so a write-back and the progress it covers are applied as
+// one atomic batch. Keys are a prefix byte followed by the big endian client or tx id.
+const ACCOUNT: u8 = b'a';
+const TX: u8 = b't';
+const CLAIM: u8 = b'c';
+/// The key of the run's `Progress`, which is absent once the run has finished.
+const PROGRESS: &[u8] = b"progress";
+/// The key of the number of runs that have finished with the store, which tells the claims of the
+/// current run from earlier ones.
+const RUNS: &[u8] = b"runs";
+
+/// How far along the run writing to a `DiskStore` was at its last write-back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Progress {
+    /// A snapshot was being loaded into the store.
+    Restoring,
+    /// The store covers the input up to and including this line.
+    Line(u64),
+}
+
+/// Keeps accounts, transactions and claims in an embedded [sled](https://docs.rs/sled) database,
+/// for a history that doesn't fit in memory.
+///
+/// The ledger persists across runs, so a run over day N+1 continues from the store day N's run
+/// left behind, the way it would from a snapshot. Values are read into a write-back cache, so a
+/// client's account and a disputed tx are only fetched once while they're busy. The cache is only
+/// written out at a checkpoint, once it's full, in one atomic batch along with the last input line
+/// it covers, so what's on disk always ends between two transactions. A run that's interrupted is
+/// resumed after that line, and one interrupted while loading a snapshot starts the load over.
+/// Claims keep their line only for the run that made them, as it's a line of that run's input.
+#[derive(Debug)]
+pub struct DiskStore {
+    ledger: sled::Tree,
+    runs: u64,
+    cached_accounts: HashMap<ClientId, Account>,
+    cached_txs: HashMap<TxId, HistoryRecord>,
+    cached_claims: HashMap<TxId, Option<u64>>,
+    pub(crate) capacity: usize,
+    checkpointed: Option<u64>,
+}
+
+impl DiskStore {
+    /// Opens the store in the directory at `path`, creating it if needed. A snapshot that was only
+    /// partly loaded is discarded.
+    pub fn open(path: &Path) -> Result<Self, EngineError> {
+        // every write-back is flushed as it's made, so sled needn't flush in the background
+        let db = sled::Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .open()
+            .map_err(io::Error::from)?;
+        let ledger = db.open_tree("ledger").map_err(io::Error::from)?;
+        let progress = ledger
+            .get(PROGRESS)
+            .map_err(io::Error::from)?
+            .map(|value| decode_progress(&value))
+            .transpose()?;
+
+        if progress == Some(Progress::Restoring) {
+            ledger.clear().map_err(io::Error::from)?;
+            ledger.flush().map_err(io::Error::from)?;
+        }
+
+        let runs = match ledger.get(RUNS).map_err(io::Error::from)? {
+            Some(value) => u64::from_be_bytes(key_bytes(&value)?),
+            None => 0,
+        };
+
+        Ok(Self {
+            ledger,
+            runs,
+            cached_accounts: HashMap::new(),
+            cached_txs: HashMap::new(),
+            cached_claims: HashMap::new(),
+            capacity: CACHE_CAPACITY,
+            checkpointed: match progress {
+                Some(Progress::Line(line)) => Some(line),
+                Some(Progress::Restoring) | None => None,
+            },
+        })
+    }
+
+    /// Writes every cached value to disk along with `progress`, or marks the run as finished when
+    /// it's `None`, flushes it and empties the cache.
+    fn write_back(&mut self, progress: Option<Progress>) -> Result<(), EngineError> {
+        let runs = match progress {
+            Some(_) => self.runs,
+            None => self.runs + 1,
+        };
+        let mut batch = sled::Batch::default();
+
+        for (client, account) in self.cached_accounts.drain() {
+            batch.insert(
+                &key(ACCOUNT, &client.0.to_be_bytes())[..],
+                &encode_account(&account)[..],
+            );
+        }
+
+        for (tx, record) in self.cached_txs.drain() {
+            batch.insert(&key(TX, &tx.0.to_be_bytes())[..], &encode_tx(&record)[..]);
+        }
+
+        for (tx, line) in self.cached_claims.drain() {
+            batch.insert(
+                &key(CLAIM, &tx.0.to_be_bytes())[..],
+                encode_claim(self.runs, line),
+            );
+        }
+
+        match progress {
+            Some(progress) => batch.insert(PROGRESS, encode_progress(progress)),
+            None => batch.remove(PROGRESS),
+        }
+
+        batch.insert(RUNS, &runs.to_be_bytes());
+        self.ledger.apply_batch(batch).map_err(io::Error::from)?;
+        self.ledger.flush().map_err(io::Error::from)?;
+        self.runs = runs;
+
+        Ok(())
+    }
+
+    fn cached(&self) -> usize {
+        self.cached_accounts.len() + self.cached_txs.len() + self.cached_claims.len()
+    }
+
+    fn get(&self, prefix: u8, id: &[u8]) -> Result<Option<sled::IVec>, EngineError> {
+        Ok(self.ledger.get(key(prefix, id)).map_err(io::Error::from)?)
+    }
+
+    /// Returns every stored value under `prefix` along with its id, in id order.
+    fn scan<const N: usize>(
+        &self,
+        prefix: u8,
+    ) -> impl Iterator<Item = Result<([u8; N], sled::IVec), EngineError>> {
+        self.ledger.scan_prefix([prefix]).map(|entry| {
+            let (key, value) = entry.map_err(io::Error::from)?;
+
+            Ok((key_bytes(&key[1..])?, value))
+        })
+    }
+}
+
+impl LedgerStore for DiskStore {
+    type Error = EngineError;
+
+    fn account(&self, client: ClientId) -> Result<Option<Account>, Self::Error> {
+        if let Some(account) = self.cached_accounts.get(&client) {
+            return Ok(Some(*account));
+        }
+
+        self.get(ACCOUNT, &client.0.to_be_bytes())?
+            .map(|value| decode_account(client, &value))
+            .transpose()
+    }
+
+    fn account_mut(&mut self, client: ClientId) -> Result<Option<&mut Account>, Self::Error> {
+        if !self.cached_accounts.contains_key(&client) {
+            let Some(account) = self.account(client)? else {
+                return Ok(None);
+            };
+
+            self.cached_accounts.insert(client, account);
+        }
+
+        Ok(self.cached_accounts.get_mut(&client))
+    }
+
+    fn insert_account(&mut self, account: Account) -> Result<(), Self::Error> {
+        self.cached_accounts.insert(account.client, account);
+
+        Ok(())
+    }
+
+    fn tx(&self, tx: TxId) -> Result<Option<HistoryRecord>, Self::Error> {
+        if let Some(cached) = self.cached_txs.get(&tx) {
+            return Ok(Some(cached.clone()));
+        }
+
+        self.get(TX, &tx.0.to_be_bytes())?
+            .map(|value| decode_tx(&value))
+            .transpose()
+    }
+
+    fn tx_mut(&mut self, tx: TxId) -> Result<Option<&mut HistoryRecord>, Self::Error> {
+        if !self.cached_txs.contains_key(&tx) {
+            let Some(stored) = self.tx(tx)? else {
+                return Ok(None);
+            };
+
+            self.cached_txs.insert(tx, stored);
+        }
+
+        Ok(self.cached_txs.get_mut(&tx))
+    }
+
+    fn insert_tx(&mut self, tx: TxId, record: HistoryRecord) -> Result<(), Self::Error> {
+        self.cached_txs.insert(tx, record);
+
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, Self::Error> {
+        let mut accounts = self.cached_accounts.clone();
+
+        for entry in self.scan(ACCOUNT) {
+            let (key, value) = entry?;
+            let client = ClientId(u16::from_be_bytes(key));
+
+            if let Entry::Vacant(vacant) = accounts.entry(client) {
+                vacant.insert(decode_account(client, &value)?);
+            }
+        }
+
+        Ok(accounts.into_values().collect())
+    }
+
+    fn transactions(
+        &self,
+    ) -> impl Iterator<Item = Result<(TxId, HistoryRecord), Self::Error>> + '_ {
+        let stored = self.scan(TX).map(|entry| {
+            let (key, value) = entry?;
+
+            Ok((TxId(u32::from_be_bytes(key)), decode_tx(&value)?))
+        });
+
+        merged(stored, sorted(&self.cached_txs))
+    }
+
+    fn claimed(&self, tx: TxId) -> Result<bool, Self::Error> {
+        if self.cached_claims.contains_key(&tx) {
+            return Ok(true);
+        }
+
+        Ok(self.get(CLAIM, &tx.0.to_be_bytes())?.is_some())
+    }
+
+    fn claimed_on(&self, tx: TxId) -> Result<Option<u64>, Self::Error> {
+        if let Some(line) = self.cached_claims.get(&tx) {
+            return Ok(*line);
+        }
+
+        match self.get(CLAIM, &tx.0.to_be_bytes())? {
+            Some(value) => decode_claim(self.runs, &value),
+            None => Ok(None),
+        }
+    }
+
+    fn insert_claim(&mut self, tx: TxId, line: Option<u64>) -> Result<(), Self::Error> {
+        self.cached_claims.insert(tx, line);
+
+        Ok(())
+    }
+
+    fn claims(&self) -> impl Iterator<Item = Result<(TxId, Option<u64>), Self::Error>> + '_ {
+        let stored = self.scan(CLAIM).map(|entry| {
+            let (key, value) = entry?;
+
+            Ok((
+                TxId(u32::from_be_bytes(key)),
+                decode_claim(self.runs, &value)?,
+            ))
+        });
+
+        merged(stored, sorted(&self.cached_claims))
+    }
+
+    fn checkpoint(&mut self, line: Option<u64>) -> Result<(), Self::Error> {
+        if self.cached() >= self.capacity {
+            self.write_back(Some(line.map_or(Progress::Restoring, Progress::Line)))?;
+        }
+
+        Ok(())
+    }
+
+    fn checkpointed(&self) -> Option<u64> {
+        self.checkpointed
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.write_back(None)?;
+        self.checkpointed = None;
+
+        Ok(())
+    }
+}
+
+/// Returns a copy of `cached` in key order. The cache never holds more than `CACHE_CAPACITY`
+/// values, so sorting it is cheap.
+fn sorted<K: Copy + Ord, V: Clone>(cached: &HashMap<K, V>) -> impl Iterator<Item = (K, V)> {
+    let mut cached: Vec<_> = cached.iter().map(|(k, v)| (*k, v.clone())).collect();
+
+    cached.sort_unstable_by_key(|(key, _)| *key);
+
+    cached.into_iter()
+}
+
+/// Merges `stored` and `cached`, each already in key order, into a single iterator in key order. A
+/// cached value replaces the stored one under the same key, which is stale. `DiskStore` keys are
+/// big endian, so a tree's byte order is also their numeric order.
+pub(crate) fn merged<'a, K, V, E>(
+    stored: impl Iterator<Item = Result<(K, V), E>> + 'a,
+    cached: impl Iterator<Item = (K, V)> + 'a,
+) -> impl Iterator<Item = Result<(K, V), E>> + 'a
+where
+    K: Copy + Ord + 'a,
+    V: 'a,
+    E: 'a,
+{
+    let mut cached = cached.peekable();
+    let mut stored = stored.peekable();
+
+    iter::from_fn(move || {
+        let stored_key = match stored.peek() {
+            Some(Ok((key, _))) => *key,
+            Some(Err(_)) => return stored.next(),
+            None => return cached.next().map(Ok),
+        };
+
+        match cached.peek() {
+            Some((key, _)) if *key <= stored_key => {
+                if *key == stored_key {
+                    stored.next();
+                }
+
+                cached.next().map(Ok)
+            }
+            _ => stored.next(),
+        }
+    })
+}
+
+impl From<Infallible> for EngineError {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+// Values are stored in a fixed binary layout. Keys hold the client or tx id, so values don't.
+
+const ACCOUNT_LEN: usize = 3 * 16 + 1;
+const TX_LEN: usize = 1 + 2 + 16 + 1 + 1 + 3 * 16 + 4;
+
+fn encode_account(account: &Account) -> [u8; ACCOUNT_LEN] {
+    let mut value = [0; ACCOUNT_LEN];
+
+    value[0..16].copy_from_slice(&account.available.serialize());
+    value[16..32].copy_from_slice(&account.held.serialize());
+    value[32..48].copy_from_slice(&account.total.serialize());
+    value[48] = u8::from(account.locked);
+
+    value
+}
+
+fn decode_account(client: ClientId, value: &[u8]) -> Result<Account, EngineError> {
+    let value: &[u8; ACCOUNT_LEN] = value.try_into().map_err(|_| corrupt("account"))?;
+
+    Ok(Account {
+        client,
+        available: decimal(&value[0..16]),
+        held: decimal(&value[16..32]),
+        total: decimal(&value[32..48]),
+        locked: value[48] != 0,
+    })
+}
+
+fn encode_tx(record: &HistoryRecord) -> [u8; TX_LEN] {
+    let mut value = [0; TX_LEN];
+
+    value[0] = match record.kind {
+        RecordKind::Deposit => 0,
+        RecordKind::Withdrawal => 1,
+        RecordKind::TimedDeposit => 2,
+        RecordKind::TimedWithdrawal => 3,
+    };
+    value[1..3].copy_from_slice(&record.client.0.to_be_bytes());
+    value[3..19].copy_from_slice(&record.amount.serialize());
+    value[19] = match record.state {
+        TransactionState::Open => 0,
+        TransactionState::ActiveDispute => 1,
+        TransactionState::ChargedBack => 2,
+    };
+
+    if let Some(disputes) = &record.disputes {
+        value[20] = 1;
+        value[21..37].copy_from_slice(&disputes.disputed.serialize());
+        value[37..53].copy_from_slice(&disputes.resolved.serialize());
+        value[53..69].copy_from_slice(&disputes.charged_back.serialize());
+    }
+
+    value[69..73].copy_from_slice(&record.seconds.to_be_bytes());
+
+    value
+}
+
+fn decode_tx(value: &[u8]) -> Result<HistoryRecord, EngineError> {
+    let value: &[u8; TX_LEN] = value.try_into().map_err(|_| corrupt("transaction"))?;
+
+    Ok(HistoryRecord {
+        amount: decimal(&value[3..19]),
+        disputes: match value[20] {
+            0 => None,
+            1 => Some(Box::new(DisputedAmounts {
+                disputed: decimal(&value[21..37]),
+                resolved: decimal(&value[37..53]),
+                charged_back: decimal(&value[53..69]),
+            })),
+            _ => return Err(corrupt("transaction")),
+        },
+        client: ClientId(u16::from_be_bytes([value[1], value[2]])),
+        kind: match value[0] {
+            0 => RecordKind::Deposit,
+            1 => RecordKind::Withdrawal,
+            2 => RecordKind::TimedDeposit,
+            3 => RecordKind::TimedWithdrawal,
+            _ => return Err(corrupt("transaction")),
+        },
+        state: match value[19] {
+            0 => TransactionState::Open,
+            1 => TransactionState::ActiveDispute,
+            2 => TransactionState::ChargedBack,
+            _ => return Err(corrupt("transaction")),
+        },
+        seconds: u32::from_be_bytes(value[69..73].try_into().expect("4 bytes")),
+    })
+}
+
+/// Encodes the line a claim was made on in the run after `runs` earlier ones, if it's known.
+fn encode_claim(runs: u64, line: Option<u64>) -> Vec<u8> {
+    line.map(|line| [runs.to_be_bytes(), line.to_be_bytes()].concat())
+        .unwrap_or_default()
+}
+
+/// Decodes the line a claim was made on, unless it's unknown or belongs to a run before the one
+/// after `runs` earlier ones.
+fn decode_claim(runs: u64, value: &[u8]) -> Result<Option<u64>, EngineError> {
+    match value.len() {
+        0 => Ok(None),
+        16 => {
+            let claimed_in = u64::from_be_bytes(value[..8].try_into().expect("8 bytes"));
+            let line = u64::from_be_bytes(value[8..].try_into().expect("8 bytes"));
+
+            Ok((claimed_in == runs).then_some(line))
+        }
+        _ => Err(corrupt("claim")),
+    }
+}
+
+fn encode_progress(progress: Progress) -> Vec<u8> {
+    match progress {
+        Progress::Restoring => Vec::new(),
+        Progress::Line(line) => line.to_be_bytes().to_vec(),
+    }
+}
+
+fn decode_progress(value: &[u8]) -> Result<Progress, EngineError> {
+    match value.len() {
+        0 => Ok(Progress::Restoring),
+        8 => Ok(Progress::Line(u64::from_be_bytes(
+            value.try_into().expect("8 bytes"),
+        ))),
+        _ => Err(corrupt("progress")),
+    }
+}
+
+/// Returns the key of the value with `id` under `prefix`.
+fn key(prefix: u8, id: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + id.len());
+
+    key.push(prefix);
+    key.extend_from_slice(id);
+
+    key
+}
+
+fn decimal(bytes: &[u8]) -> Decimal {
+    // the callers always slice exactly 16 bytes
+    Decimal::deserialize(bytes.try_into().expect("16 bytes"))
+}
+
+fn key_bytes<const N: usize>(key: &[u8]) -> Result<[u8; N], EngineError> {
+    key.try_into().map_err(|_| corrupt("key"))
+}
+
+fn corrupt(what: &str) -> EngineError {
+    EngineError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt {what} in the ledger store"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("payments-engine-{}-store-{name}", process::id()))
+    }
+
+    fn deposit(client: u16) -> HistoryRecord {
+        HistoryRecord {
+            amount: dec!(1.5),
+            disputes: None,
+            client: ClientId(client),
+            kind: RecordKind::Deposit,
+            state: TransactionState::Open,
+            seconds: 0,
+        }
+    }
+
+    /// Inserts, reads and updates through `store`, the same way the engine does.
+    fn get_and_insert<S: LedgerStore>(store: &mut S) -> Result<(), S::Error> {
+        assert_eq!(None, store.account(ClientId(1u16))?);
+        assert_eq!(None, store.account_mut(ClientId(1u16))?);
+        assert_eq!(None, store.tx(TxId(1u32))?);
+        assert!(!store.claimed(TxId(1u32))?);
+
+        store.insert_account(Account::new(ClientId(1u16)))?;
+        store.insert_tx(TxId(1u32), deposit(1))?;
+        store.insert_claim(TxId(2u32), None)?;
+        store.insert_claim(TxId(1u32), Some(7))?;
+
+        let account = store.account_mut(ClientId(1u16))?.unwrap();
+
+        account.available = dec!(1.5);
+        account.total = dec!(1.5);
+
+        store.tx_mut(TxId(1u32))?.unwrap().state = TransactionState::ActiveDispute;
+
+        assert_eq!(dec!(1.5), store.account(ClientId(1u16))?.unwrap().total);
+        assert_eq!(
+            TransactionState::ActiveDispute,
+            store.tx(TxId(1u32))?.unwrap().state
+        );
+        assert!(store.claimed(TxId(2u32))?);
+        assert_eq!(None, store.claimed_on(TxId(2u32))?);
+        assert_eq!(Some(7), store.claimed_on(TxId(1u32))?);
+        assert_eq!(1, store.accounts()?.len());
+        assert_eq!(1, store.transactions().count());
+        assert_eq!(
+            vec![(TxId(1u32), Some(7)), (TxId(2u32), None)],
+            store.claims().collect::<Result<Vec<_>, _>>()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store() {
+        let Ok(()) = get_and_insert(&mut MemoryStore::default());
+    }
+
+    #[test]
+    fn disk_store() {
+        let dir = dir("disk");
+        let mut store = DiskStore::open(&dir).unwrap();
+
+        get_and_insert(&mut store).unwrap();
+        store.finish().unwrap();
+        drop(store);
+
+        // the ledger carries over to the next run, which starts on a new input
+        let store = DiskStore::open(&dir).unwrap();
+
+        assert_eq!(None, store.checkpointed());
+        assert_eq!(
+            dec!(1.5),
+            store.account(ClientId(1u16)).unwrap().unwrap().total
+        );
+        assert_eq!(
+            TransactionState::ActiveDispute,
+            store.tx(TxId(1u32)).unwrap().unwrap().state
+        );
+        assert!(store.claimed(TxId(1u32)).unwrap());
+        assert_eq!(None, store.claimed_on(TxId(1u32)).unwrap());
+        assert_eq!(2, store.claims().count());
+
+        drop(store);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoints() {
+        let dir = dir("checkpoints");
+        let mut store = DiskStore::open(&dir).unwrap();
+
+        store.capacity = 4;
+
+        // each line claims a tx id and stores its deposit, so every other line fills the cache
+        for line in 1..=5u32 {
+            store
+                .insert_claim(TxId(line), Some(u64::from(line)))
+                .unwrap();
+            store.insert_tx(TxId(line), deposit(1)).unwrap();
+            store.checkpoint(Some(u64::from(line))).unwrap();
+        }
+
+        drop(store);
+
+        // an interrupted run is left at its last write-back, with nothing of the line after it
+        let mut store = DiskStore::open(&dir).unwrap();
+
+        assert_eq!(Some(4), store.checkpointed());
+        assert_eq!(4, store.claims().count());
+        assert_eq!(4, store.transactions().count());
+        assert!(!store.claimed(TxId(5u32)).unwrap());
+
+        store.finish().unwrap();
+        drop(store);
+
+        // a snapshot that was cut short is loaded again from the start
+        let mut store = DiskStore::open(&dir).unwrap();
+
+        store.capacity = 1;
+        store.insert_claim(TxId(9u32), None).unwrap();
+        store.checkpoint(None).unwrap();
+        drop(store);
+
+        let store = DiskStore::open(&dir).unwrap();
+
+        assert_eq!(None, store.checkpointed());
+        assert_eq!(0, store.claims().count());
+        assert_eq!(None, store.tx(TxId(1u32)).unwrap());
+
+        drop(store);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_back() {
+        let dir = dir("write-back");
+        let mut store = DiskStore::open(&dir).unwrap();
+
+        store.capacity = 2;
+
+        for tx in 0..10u32 {
+            store.insert_tx(TxId(tx), deposit((tx % 3) as u16)).unwrap();
+            store.insert_claim(TxId(tx), Some(u64::from(tx))).unwrap();
+            store.checkpoint(Some(u64::from(tx))).unwrap();
+        }
+
+        // an update to a value that was written back is written back as well
+        store.tx_mut(TxId(0u32)).unwrap().unwrap().state = TransactionState::ChargedBack;
+
+        for tx in 10..20 {
+            store.insert_tx(TxId(tx), deposit(1)).unwrap();
+            store.checkpoint(Some(u64::from(tx))).unwrap();
+        }
+
+        assert!(store.cached() < 2);
+        assert_eq!(Some(9), store.claimed_on(TxId(9u32)).unwrap());
+        assert_eq!(deposit(2), store.tx(TxId(5u32)).unwrap().unwrap());
+        assert_eq!(
+            TransactionState::ChargedBack,
+            store.tx(TxId(0u32)).unwrap().unwrap().state
+        );
+
+        // a cached update of a value that was written back replaces it
+        store.tx_mut(TxId(5u32)).unwrap().unwrap().state = TransactionState::ActiveDispute;
+
+        // the written back and the cached values come out together, in tx id order
+        let txs: Vec<_> = store.transactions().map(Result::unwrap).collect();
+
+        assert_eq!(
+            (0..20).collect::<Vec<_>>(),
+            txs.iter().map(|(tx, _)| tx.0).collect::<Vec<_>>()
+        );
+        assert_eq!(TransactionState::ActiveDispute, txs[5].1.state);
+        assert_eq!(10, store.claims().count());
+
+        drop(store);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encoding() {
+        let account = Account {
+            client: ClientId(7u16),
+            available: dec!(-1.2345),
+            held: dec!(79228162514264.3375935439503),
+            total: Decimal::ZERO,
+            locked: true,
+        };
+        let disputed = HistoryRecord {
+            amount: dec!(3.5),
+            disputes: Some(Box::new(DisputedAmounts {
+                disputed: dec!(1),
+                resolved: dec!(0.0001),
+                charged_back: dec!(2.5),
+            })),
+            client: ClientId(7u16),
+            kind: RecordKind::TimedWithdrawal,
+            state: TransactionState::ActiveDispute,
+            seconds: u32::MAX,
+        };
+
+        assert_eq!(
+            account,
+            decode_account(account.client, &encode_account(&account)).unwrap()
+        );
+        assert_eq!(disputed, decode_tx(&encode_tx(&disputed)).unwrap());
+        assert_eq!(deposit(1), decode_tx(&encode_tx(&deposit(1))).unwrap());
+        assert!(decode_account(account.client, &[0; 3]).is_err());
+        assert!(decode_tx(&[9; TX_LEN]).is_err());
+        assert_eq!(
+            Some(42),
+            decode_claim(3, &encode_claim(3, Some(42))).unwrap()
+        );
+        assert_eq!(None, decode_claim(3, &encode_claim(3, None)).unwrap());
+        // the line of a claim from an earlier run belongs to another input
+        assert_eq!(None, decode_claim(4, &encode_claim(3, Some(42))).unwrap());
+        assert!(decode_claim(3, &[0; 3]).is_err());
+    }
+}
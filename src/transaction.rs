@@ -1,15 +1,39 @@
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-const DECIMAL_PRECISION: u32 = 4;
+use crate::de;
 
-#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+pub(crate) const DECIMAL_PRECISION: u32 = 4;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct ClientId(pub u16);
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        de::id(deserializer).map(Self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct TxId(pub u32);
 
-#[derive(Debug, Deserialize, PartialEq)]
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        de::id(deserializer).map(Self)
+    }
+}
+
+/// When a transaction happened, in whole seconds since the Unix epoch. Four bytes reach into 2106.
+#[derive(Clone, Copy, Debug, Serialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp(pub u32);
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        de::id(deserializer).map(Self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "lowercase")]
 pub enum TransactionType {
     Chargeback,
@@ -19,28 +43,39 @@ pub enum TransactionType {
     Withdrawal,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionState {
+    #[default]
     Open,
     ActiveDispute,
     ChargedBack,
 }
 
-impl Default for TransactionState {
-    fn default() -> Self {
-        Self::Open
-    }
+/// The portions of a transaction's amount that are actively disputed, have been resolved and
+/// have been charged back. Disputes, resolves and chargebacks can each cover part of the amount.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisputedAmounts {
+    pub disputed: Decimal,
+    pub resolved: Decimal,
+    pub charged_back: Decimal,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub _type: TransactionType,
     pub client: ClientId,
     pub tx: TxId,
+    #[serde(default, deserialize_with = "de::amount")]
     pub amount: Option<Decimal>,
     #[serde(skip)]
     pub state: TransactionState,
+    #[serde(skip)]
+    pub disputes: DisputedAmounts,
+    /// When the transaction happened, if the input has a `timestamp` column.
+    #[serde(default, deserialize_with = "de::timestamp")]
+    pub timestamp: Option<Timestamp>,
 }
 
 impl Transaction {
@@ -56,6 +91,32 @@ impl Transaction {
         !amount.is_sign_negative() && amount.scale() <= DECIMAL_PRECISION
     }
 
+    /// Returns the portion of `amount` that can still be disputed, which excludes anything
+    /// actively disputed or already charged back.
+    pub fn disputable(&self) -> Decimal {
+        self.amount.unwrap_or_default() - self.disputes.disputed - self.disputes.charged_back
+    }
+
+    /// Derives `state` from the disputed portions. A tx is only considered charged back once its
+    /// whole amount has been charged back.
+    pub fn update_state(&mut self) {
+        self.state = if !self.disputes.disputed.is_zero() {
+            TransactionState::ActiveDispute
+        } else if self.disputes.charged_back == self.amount.unwrap_or_default() {
+            TransactionState::ChargedBack
+        } else {
+            TransactionState::Open
+        };
+    }
+
+    /// Returns the seconds between `earlier` and this transaction, when both have a timestamp. A
+    /// transaction timestamped before `earlier` is zero seconds after it.
+    pub fn seconds_since(&self, earlier: &Transaction) -> Option<u64> {
+        Some(u64::from(
+            self.timestamp?.0.saturating_sub(earlier.timestamp?.0),
+        ))
+    }
+
     /// Returns a `bool` representing transaction types that should be tracked for global
     /// uniqueness. Put another way, transaction types that have tx pointers to existin txs cannot
     /// be unique.
@@ -72,74 +133,200 @@ impl Transaction {
     /// txs can reference them.
     pub fn requires_history(&self) -> bool {
         match self._type {
-            TransactionType::Deposit => true,
-            TransactionType::Withdrawal
-            | TransactionType::Dispute
-            | TransactionType::Resolve
-            | TransactionType::Chargeback => false,
+            TransactionType::Deposit | TransactionType::Withdrawal => true,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                false
+            }
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
-    use serial_test::serial;
+impl Transaction {
+    /// An open transaction with nothing disputed and no timestamp, as most tests need one.
+    pub(crate) fn new(
+        _type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            _type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount,
+            state: TransactionState::Open,
+            disputes: DisputedAmounts::default(),
+            timestamp: None,
+        }
+    }
+}
+
+/// Whether a `HistoryRecord` is a deposit or a withdrawal and whether it has a timestamp, in one
+/// byte. That leaves the record's four timestamp bytes the whole `u32` range, where an
+/// `Option<Timestamp>` would take eight and grow every record by half.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordKind {
+    Deposit,
+    Withdrawal,
+    TimedDeposit,
+    TimedWithdrawal,
+}
+
+impl RecordKind {
+    pub fn new(withdrawal: bool, timed: bool) -> Self {
+        match (withdrawal, timed) {
+            (false, false) => Self::Deposit,
+            (true, false) => Self::Withdrawal,
+            (false, true) => Self::TimedDeposit,
+            (true, true) => Self::TimedWithdrawal,
+        }
+    }
+
+    pub fn withdrawal(self) -> bool {
+        matches!(self, Self::Withdrawal | Self::TimedWithdrawal)
+    }
 
+    pub fn timed(self) -> bool {
+        matches!(self, Self::TimedDeposit | Self::TimedWithdrawal)
+    }
+}
+
+/// A deposit or withdrawal as kept in the history, holding only what later disputes, resolves and
+/// chargebacks need. The tx id is what the record is stored under, and the disputed amounts are
+/// only allocated once the tx is first disputed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryRecord {
+    pub amount: Decimal,
+    pub disputes: Option<Box<DisputedAmounts>>,
+    pub client: ClientId,
+    pub kind: RecordKind,
+    pub state: TransactionState,
+    /// The seconds of the tx's timestamp, zero when `kind` says it has none.
+    pub seconds: u32,
+}
+
+impl HistoryRecord {
+    /// Returns the tx's timestamp, if it has one.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.kind.timed().then_some(Timestamp(self.seconds))
+    }
+
+    /// Returns the full transaction for the record stored under `tx`.
+    pub fn transaction(&self, tx: TxId) -> Transaction {
+        Transaction {
+            _type: match self.kind.withdrawal() {
+                true => TransactionType::Withdrawal,
+                false => TransactionType::Deposit,
+            },
+            client: self.client,
+            tx,
+            amount: Some(self.amount),
+            state: self.state,
+            disputes: self.disputes.as_deref().copied().unwrap_or_default(),
+            timestamp: self.timestamp(),
+        }
+    }
+}
+
+impl From<&Transaction> for HistoryRecord {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            amount: tx.amount.unwrap_or_default(),
+            disputes: (tx.disputes != DisputedAmounts::default()).then(|| Box::new(tx.disputes)),
+            client: tx.client,
+            kind: RecordKind::new(
+                tx._type == TransactionType::Withdrawal,
+                tx.timestamp.is_some(),
+            ),
+            state: tx.state,
+            seconds: tx.timestamp.map_or(0, |timestamp| timestamp.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
 
     #[test]
-    #[serial]
     fn valid_tx() {
-        let actual = Transaction {
-            _type: TransactionType::Deposit,
-            client: ClientId(1u16),
-            tx: TxId(1u32),
-            amount: Some(Decimal::ONE),
-            state: TransactionState::Open,
-        };
+        let actual = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::ONE));
 
         assert!(actual.valid_tx_data());
     }
 
     #[test]
-    #[serial]
     fn valid_tx_boundary() {
-        let actual = Transaction {
-            _type: TransactionType::Deposit,
-            client: ClientId(1u16),
-            tx: TxId(1u32),
-            amount: Some(Decimal::new(123456, 4)),
-            state: TransactionState::Open,
-        };
+        let actual = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Decimal::new(123456, 4)),
+        );
 
         assert!(actual.valid_tx_data());
     }
 
     #[test]
-    #[serial]
     fn invalid_tx_boundary() {
-        let actual = Transaction {
-            _type: TransactionType::Deposit,
-            client: ClientId(1u16),
-            tx: TxId(1u32),
-            amount: Some(Decimal::new(123456, 5)),
-            state: TransactionState::Open,
-        };
+        let actual = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Decimal::new(123456, 5)),
+        );
 
         assert!(!actual.valid_tx_data());
     }
 
     #[test]
-    #[serial]
     fn invalid_tx() {
-        let actual = Transaction {
-            _type: TransactionType::Deposit,
-            client: ClientId(1u16),
-            tx: TxId(1u32),
-            amount: Some(Decimal::new(123456789101112, 10)),
+        let actual = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Decimal::new(123456789101112, 10)),
+        );
+
+        assert!(!actual.valid_tx_data());
+    }
+
+    #[test]
+    fn history_record() {
+        let mut tx = Transaction {
+            _type: TransactionType::Withdrawal,
+            client: ClientId(3u16),
+            tx: TxId(9u32),
+            amount: Some(Decimal::TEN),
             state: TransactionState::Open,
+            disputes: DisputedAmounts::default(),
+            timestamp: Some(Timestamp(7)),
         };
+        let record = HistoryRecord::from(&tx);
 
-        assert!(!actual.valid_tx_data());
+        assert_eq!(None, record.disputes);
+        assert_eq!(tx, record.transaction(tx.tx));
+
+        tx.disputes.disputed = Decimal::ONE;
+        tx.update_state();
+
+        let record = HistoryRecord::from(&tx);
+
+        assert_eq!(Some(Box::new(tx.disputes)), record.disputes);
+        assert_eq!(tx, record.transaction(tx.tx));
+        // the timestamp fits in the padding after the client, kind and state
+        assert_eq!(32, std::mem::size_of::<HistoryRecord>());
+
+        for timestamp in [None, Some(Timestamp(0)), Some(Timestamp(u32::MAX))] {
+            for _type in [TransactionType::Deposit, TransactionType::Withdrawal] {
+                let tx = Transaction {
+                    timestamp,
+                    ..Transaction::new(_type, 3, 9, Some(Decimal::TEN))
+                };
+
+                assert_eq!(tx, HistoryRecord::from(&tx).transaction(tx.tx));
+            }
+        }
     }
 }